@@ -0,0 +1,260 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use log::{info, warn};
+
+use crate::{
+    config::{AutoProfileConfig, ProfileRule},
+    control::Controller,
+    supervisor::Supervisor,
+};
+
+/// Activates the profile of the first rule matching a running process, and the fallback
+/// profile once none matches anymore
+pub struct ProcessWatcher {
+    proc_root: PathBuf,
+    config: AutoProfileConfig,
+
+    /// Profile the rules picked on the last scan, `None` before the first one. Only changes
+    /// lead to a switch, so profiles activated by hand stay until a game starts or exits.
+    picked: Option<String>,
+}
+
+impl ProcessWatcher {
+    pub fn spawn(
+        config: AutoProfileConfig,
+        controller: Arc<Mutex<Controller>>,
+        supervisor: &Supervisor,
+    ) {
+        if config.rules.is_empty() {
+            return;
+        }
+
+        let mut watcher = Self {
+            proc_root: PathBuf::from("/proc"),
+            config,
+            picked: None,
+        };
+
+        supervisor.spawn("process watcher", move || loop {
+            watcher.check(&controller);
+
+            thread::sleep(Duration::from_secs_f32(watcher.config.interval));
+        });
+    }
+
+    fn check(&mut self, controller: &Mutex<Controller>) {
+        let processes = running_processes(&self.proc_root);
+
+        let Some((profile, reason)) = self.pick(&processes) else {
+            return;
+        };
+
+        info!("switching to profile `{profile}`: {reason}");
+
+        // not saved, the config keeps the profile the user chose
+        if let Err(err) = controller.lock().unwrap().switch_profile(&profile) {
+            warn!("failed to switch profile: {err:#}");
+        }
+    }
+
+    /// Profile to switch to and why, if the rules picked a different one than last time. A
+    /// failed switch isn't retried every scan, only on the next change.
+    fn pick(&mut self, processes: &[Process]) -> Option<(String, String)> {
+        let matched = matching_rule(&self.config.rules, processes);
+
+        let (profile, reason) = match matched {
+            Some((rule, process)) => (&rule.profile, format!("{} is running", process.name)),
+            None => (
+                &self.config.fallback,
+                "no configured game is running".to_string(),
+            ),
+        };
+
+        let previous = self.picked.replace(profile.clone());
+
+        match previous {
+            Some(previous) if previous == *profile => None,
+
+            // the profile chosen at startup stays unless a game is running already, the
+            // fallback only applies once one exits
+            None if matched.is_none() => None,
+
+            _ => Some((profile.clone(), reason)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Process {
+    /// Name of the executable, without directory
+    name: String,
+
+    /// Arguments joined by spaces
+    cmdline: String,
+}
+
+impl Process {
+    fn matches(&self, rule: &ProfileRule) -> bool {
+        rule.executables
+            .iter()
+            .any(|executable| executable.eq_ignore_ascii_case(&self.name))
+            || rule
+                .cmdline
+                .iter()
+                .any(|pattern| self.cmdline.contains(pattern.as_str()))
+    }
+}
+
+fn matching_rule<'a, 'b>(
+    rules: &'a [ProfileRule],
+    processes: &'b [Process],
+) -> Option<(&'a ProfileRule, &'b Process)> {
+    rules.iter().find_map(|rule| {
+        processes
+            .iter()
+            .find(|process| process.matches(rule))
+            .map(|process| (rule, process))
+    })
+}
+
+/// Processes that vanish or can't be read while scanning are skipped
+fn running_processes(proc_root: &Path) -> Vec<Process> {
+    let Ok(entries) = fs::read_dir(proc_root) else {
+        return Vec::new();
+    };
+
+    entries
+        .flatten()
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.bytes().all(|b| b.is_ascii_digit()))
+        })
+        .filter_map(|entry| read_process(&entry.path()))
+        .collect()
+}
+
+fn read_process(dir: &Path) -> Option<Process> {
+    let cmdline = fs::read(dir.join("cmdline")).ok()?;
+    let args: Vec<String> = cmdline
+        .split(|b| *b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+
+    // wine and proton games show up with their windows path in argv[0]
+    let name = match args.first() {
+        Some(arg) => arg.rsplit(['/', '\\']).next().unwrap_or(arg).to_string(),
+
+        // kernel threads have no command line
+        None => fs::read_to_string(dir.join("comm"))
+            .ok()?
+            .trim()
+            .to_string(),
+    };
+
+    Some(Process {
+        name,
+        cmdline: args.join(" "),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, process};
+
+    use crate::config::{AutoProfileConfig, ProfileRule};
+
+    use super::{matching_rule, running_processes, Process, ProcessWatcher};
+
+    fn rule(profile: &str, executables: &[&str], cmdline: &[&str]) -> ProfileRule {
+        ProfileRule {
+            profile: profile.to_string(),
+            executables: executables.iter().map(|s| s.to_string()).collect(),
+            cmdline: cmdline.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn scan_proc() {
+        let root = env::temp_dir().join(format!("xr_to_opentrack_proc_{}", process::id()));
+
+        for (pid, cmdline) in [
+            ("1", b"/sbin/init\0splash\0".as_slice()),
+            (
+                "42",
+                b"Z:\\games\\ACC\\AC2-Win64-Shipping.exe\0-dx12\0".as_slice(),
+            ),
+            (
+                "77",
+                b"/usr/bin/python3\0/opt/sims/flight.py\0--vr\0".as_slice(),
+            ),
+        ] {
+            fs::create_dir_all(root.join(pid)).unwrap();
+            fs::write(root.join(pid).join("cmdline"), cmdline).unwrap();
+        }
+
+        // not a process
+        fs::create_dir_all(root.join("self")).unwrap();
+
+        let processes = running_processes(&root);
+        assert_eq!(processes.len(), 3);
+
+        let rules = [
+            rule("racing", &["ac2-win64-shipping.exe"], &[]),
+            rule("flight", &[], &["flight.py --vr"]),
+        ];
+
+        let (matched, process) = matching_rule(&rules, &processes).unwrap();
+        assert_eq!(matched.profile, "racing");
+        assert_eq!(process.name, "AC2-Win64-Shipping.exe");
+
+        let (matched, _) = matching_rule(&rules[1..], &processes).unwrap();
+        assert_eq!(matched.profile, "flight");
+
+        assert!(matching_rule(&[rule("none", &["flight.py"], &["steam"])], &processes).is_none());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn switch_on_changes() {
+        let game = |name: &str| Process {
+            name: name.to_string(),
+            cmdline: name.to_string(),
+        };
+
+        let mut watcher = ProcessWatcher {
+            proc_root: env::temp_dir(),
+            config: AutoProfileConfig {
+                rules: vec![rule("racing", &["acc.exe"], &[])],
+                ..Default::default()
+            },
+            picked: None,
+        };
+
+        // no game at startup keeps the profile the daemon started with
+        assert_eq!(watcher.pick(&[game("bash")]), None);
+
+        let (profile, _) = watcher.pick(&[game("acc.exe")]).unwrap();
+        assert_eq!(profile, "racing");
+        assert_eq!(watcher.pick(&[game("acc.exe")]), None);
+
+        let (profile, _) = watcher.pick(&[game("bash")]).unwrap();
+        assert_eq!(profile, "default");
+        assert_eq!(watcher.pick(&[]), None);
+
+        // a game running at startup gets its profile right away
+        watcher.picked = None;
+
+        let (profile, _) = watcher.pick(&[game("acc.exe")]).unwrap();
+        assert_eq!(profile, "racing");
+    }
+}
@@ -0,0 +1,845 @@
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::{self, Write},
+    net::SocketAddr,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    process,
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use toml::Table;
+use toml_edit::{value, DocumentMut, Item};
+
+use crate::{
+    control::{auth::ControlToken, endpoint::ControlEndpoint, ControlClient},
+    euler::{EulerData, EulerSettings},
+    hotplug::DevicePreference,
+    logging::LogFilter,
+    open_track_data::PoseField,
+    open_track_target::Destination,
+    profile::{Profiles, DEFAULT_PROFILE},
+    scheduler::{OutputMode, OutputScheduler},
+};
+
+const CONFIG_DIR: &str = "xr_to_opentrack";
+const CONFIG_FILE: &str = "config.toml";
+
+/// Daemon settings as read from the config files. Missing keys keep their defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Address on which OpenTrack listens
+    pub target: Destination,
+
+    /// Fixed output rate in Hz, unset sends once per IMU sample
+    pub rate: Option<f32>,
+    pub output_mode: OutputMode,
+
+    pub relay: RelayConfig,
+
+    pub device: DeviceConfig,
+
+    pub euler: EulerSettings,
+
+    /// Reference pose to recenter on
+    pub center: Option<EulerData>,
+
+    /// Active profile, `default` being the `euler` table
+    pub profile: String,
+
+    /// Further named settings next to the `euler` table
+    pub profiles: BTreeMap<String, EulerSettings>,
+
+    pub control: ControlConfig,
+
+    /// Address of the HTTP endpoint serving Prometheus metrics, unset disables it
+    pub metrics: Option<SocketAddr>,
+
+    /// Write changes made over the control channel back to the config file right away
+    pub autosave: bool,
+
+    pub auto_profile: AutoProfileConfig,
+
+    /// Log level, optionally per module, e.g. `info,relay=debug`
+    pub log_level: String,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            target: Destination {
+                host: "127.0.0.1".to_string(),
+                port: Destination::DEFAULT_PORT,
+            },
+
+            rate: None,
+            output_mode: OutputMode::Interpolate,
+
+            relay: RelayConfig::default(),
+
+            device: DeviceConfig::default(),
+
+            euler: EulerSettings::default(),
+            center: None,
+
+            profile: DEFAULT_PROFILE.to_string(),
+            profiles: BTreeMap::new(),
+
+            control: ControlConfig::default(),
+
+            metrics: None,
+
+            autosave: false,
+
+            auto_profile: AutoProfileConfig::default(),
+
+            log_level: LogFilter::default().to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelayConfig {
+    /// Address to receive OpenTrack UDP packets of another tracker on. They only ride along
+    /// with the poses of the glasses, relay input alone isn't forwarded.
+    pub address: Option<SocketAddr>,
+
+    /// Fields taken from the relay input
+    pub fields: Vec<PoseField>,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            address: None,
+            fields: vec![PoseField::X, PoseField::Y, PoseField::Z],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceConfig {
+    /// Seconds without IMU samples after which a connected device counts as stalled
+    pub stall_timeout: f32,
+
+    /// The only glasses allowed to drive the output. The SDK can't be told which ones to open,
+    /// so streaming starts once they are the only supported ones attached.
+    pub prefer: Option<DevicePreference>,
+}
+
+impl DeviceConfig {
+    /// Supported stall timeouts in seconds
+    pub const STALL_TIMEOUTS: RangeInclusive<f32> = 0.1..=60.0;
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        Self {
+            stall_timeout: 2.0,
+            prefer: None,
+        }
+    }
+}
+
+/// Switches profiles depending on the running processes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoProfileConfig {
+    /// Seconds between two scans of `/proc`
+    pub interval: f32,
+
+    /// Profile activated once no rule matches anymore
+    pub fallback: String,
+
+    /// Checked in order, the first matching rule wins
+    pub rules: Vec<ProfileRule>,
+}
+
+impl AutoProfileConfig {
+    /// Supported scan intervals in seconds
+    pub const INTERVALS: RangeInclusive<f32> = 0.1..=60.0;
+}
+
+impl Default for AutoProfileConfig {
+    fn default() -> Self {
+        Self {
+            interval: 2.0,
+            fallback: DEFAULT_PROFILE.to_string(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileRule {
+    pub profile: String,
+
+    /// Executable names, compared case insensitively and without directory
+    #[serde(default)]
+    pub executables: Vec<String>,
+
+    /// Substrings of the command line
+    #[serde(default)]
+    pub cmdline: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControlConfig {
+    pub socket: Option<PathBuf>,
+    /// Where the daemon additionally listens, clients keep using the socket
+    pub tcp: Option<SocketAddr>,
+    pub token_file: Option<PathBuf>,
+    /// Address clients connect to instead of the socket, only given on the command line
+    #[serde(skip)]
+    pub client_tcp: Option<SocketAddr>,
+}
+
+impl ControlConfig {
+    /// Endpoint clients use to reach the daemon
+    pub fn endpoint(&self) -> ControlEndpoint {
+        match (self.client_tcp, &self.socket) {
+            (Some(address), _) => ControlEndpoint::Tcp(address),
+            (None, Some(path)) => ControlEndpoint::Unix(path.clone()),
+            (None, None) => ControlEndpoint::Unix(ControlEndpoint::default_client_socket_path()),
+        }
+    }
+
+    pub fn socket_path(&self) -> PathBuf {
+        self.socket
+            .clone()
+            .unwrap_or_else(ControlEndpoint::default_socket_path)
+    }
+
+    pub fn token(&self) -> Result<Option<ControlToken>> {
+        self.token_file
+            .as_deref()
+            .map(ControlToken::from_file)
+            .transpose()
+    }
+
+    /// Connects to the daemon, authenticating on TCP if a token is configured
+    pub fn connect(&self) -> Result<ControlClient> {
+        let endpoint = self.endpoint();
+
+        let token = match endpoint {
+            ControlEndpoint::Tcp(_) => self.token()?,
+            ControlEndpoint::Unix(_) => None,
+        };
+
+        ControlClient::connect(&endpoint, token.as_ref())
+    }
+}
+
+impl Config {
+    pub fn system_path() -> PathBuf {
+        Path::new("/etc").join(CONFIG_DIR).join(CONFIG_FILE)
+    }
+
+    pub fn user_path() -> Option<PathBuf> {
+        let config_home = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+
+        Some(config_home.join(CONFIG_DIR).join(CONFIG_FILE))
+    }
+
+    /// Files [`Config::load`] reads, in increasing precedence, whether they exist or not
+    pub fn paths(path: Option<&Path>) -> Vec<PathBuf> {
+        match path {
+            Some(path) => vec![path.to_path_buf()],
+            None => [Some(Self::system_path()), Self::user_path()]
+                .into_iter()
+                .flatten()
+                .collect(),
+        }
+    }
+
+    /// File runtime changes are saved to: the given file, otherwise the user config. Only root
+    /// saves to the system config, if that is the most specific existing one.
+    pub fn save_path(path: Option<&Path>) -> PathBuf {
+        if let Some(path) = path {
+            return path.to_path_buf();
+        }
+
+        let root = unsafe { libc::geteuid() } == 0;
+
+        Self::pick_save_path(Self::user_path(), Self::system_path(), root)
+    }
+
+    fn pick_save_path(user_path: Option<PathBuf>, system_path: PathBuf, root: bool) -> PathBuf {
+        match user_path {
+            // the system config belongs to root, others couldn't write it anyway
+            Some(user_path) if !root || user_path.exists() || !system_path.exists() => user_path,
+            _ => system_path,
+        }
+    }
+
+    /// Loads the given file, or layers the user config over the system config. Files that
+    /// don't exist are skipped, unless explicitly given.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let mut merged = Table::new();
+
+        match path {
+            Some(path) => merge_tables(&mut merged, Self::read_file(path)?),
+            None => {
+                for path in Self::paths(None) {
+                    if path.exists() {
+                        merge_tables(&mut merged, Self::read_file(&path)?);
+                    }
+                }
+            }
+        }
+
+        let config: Self = merged.try_into()?;
+
+        // profiles and their users may be spread over the files
+        config
+            .validate()
+            .context("invalid combination of config files")?;
+
+        Ok(config)
+    }
+
+    /// Parses and validates a single file, errors name the file and the offending key
+    fn read_file(path: &Path) -> Result<Table> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read config {}", path.display()))?;
+
+        Self::parse(&content).with_context(|| format!("invalid config {}", path.display()))
+    }
+
+    fn parse(content: &str) -> Result<Table> {
+        // deserializing from the text keeps line and key information in the error
+        let config: Self = toml::from_str(content)?;
+        config.validate_values()?;
+
+        Ok(content.parse()?)
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        self.validate_values()?;
+        self.validate_combinations()
+    }
+
+    /// Checks every value on its own, each file has to pass this
+    fn validate_values(&self) -> Result<()> {
+        if let Some(rate) = self.rate {
+            let rates = OutputScheduler::RATES;
+
+            if !rates.contains(&rate) {
+                bail!(
+                    "key `rate`: has to be between {} and {} Hz (got {rate})",
+                    rates.start(),
+                    rates.end()
+                );
+            }
+        }
+
+        let stall_timeout = self.device.stall_timeout;
+        let stall_timeouts = DeviceConfig::STALL_TIMEOUTS;
+
+        if !stall_timeouts.contains(&stall_timeout) {
+            bail!(
+                "key `device.stall_timeout`: has to be between {} and {} seconds (got \
+                 {stall_timeout})",
+                stall_timeouts.start(),
+                stall_timeouts.end()
+            );
+        }
+
+        self.euler.validate("euler")?;
+
+        for (name, settings) in &self.profiles {
+            settings.validate(&format!("profiles.{name}"))?;
+        }
+
+        let auto_profile = &self.auto_profile;
+
+        let intervals = AutoProfileConfig::INTERVALS;
+
+        if !intervals.contains(&auto_profile.interval) {
+            bail!(
+                "key `auto_profile.interval`: has to be between {} and {} seconds (got {})",
+                intervals.start(),
+                intervals.end(),
+                auto_profile.interval
+            );
+        }
+
+        for (index, rule) in auto_profile.rules.iter().enumerate() {
+            if rule.executables.is_empty() && rule.cmdline.is_empty() {
+                bail!("key `auto_profile.rules[{index}]`: needs `executables` or `cmdline`");
+            }
+        }
+
+        self.log_filter()?;
+
+        Ok(())
+    }
+
+    /// Checks keys that depend on each other, like a profile and where it is used. They may
+    /// be set in different files, so this only holds once all files are merged.
+    fn validate_combinations(&self) -> Result<()> {
+        let profiles = self.profiles()?;
+
+        let auto_profile = &self.auto_profile;
+
+        if profiles.get(&auto_profile.fallback).is_none() {
+            bail!(
+                "key `auto_profile.fallback`: profile `{}` does not exist",
+                auto_profile.fallback
+            );
+        }
+
+        for (index, rule) in auto_profile.rules.iter().enumerate() {
+            if profiles.get(&rule.profile).is_none() {
+                bail!(
+                    "key `auto_profile.rules[{index}].profile`: profile `{}` does not exist",
+                    rule.profile
+                );
+            }
+        }
+
+        if let Some(address) = self.control.tcp {
+            if !address.ip().is_loopback() && self.control.token_file.is_none() {
+                bail!("key `control.tcp`: binding {address} requires `control.token_file`");
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn profiles(&self) -> Result<Profiles> {
+        Profiles::new(&self.profile, self.euler, &self.profiles)
+    }
+
+    pub fn log_filter(&self) -> Result<LogFilter> {
+        self.log_level.parse().context("key `log_level`")
+    }
+}
+
+/// Writes runtime changes of the [`EulerState`] back into a config file
+pub struct ConfigWriter {
+    path: PathBuf,
+    autosave: bool,
+}
+
+impl ConfigWriter {
+    pub fn new(path: PathBuf, autosave: bool) -> Self {
+        Self { path, autosave }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn autosave(&self) -> bool {
+        self.autosave
+    }
+
+    pub fn set_autosave(&mut self, autosave: bool) {
+        self.autosave = autosave;
+    }
+
+    /// Updates center and profiles, leaving all other keys and comments as they are. The file
+    /// is replaced atomically, so a crash never leaves a half written config behind.
+    pub fn save(&self, center: Option<EulerData>, profiles: &Profiles) -> Result<()> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("failed to read config {}", self.path.display()))
+            }
+        };
+
+        let mut document: DocumentMut = content
+            .parse()
+            .with_context(|| format!("invalid config {}", self.path.display()))?;
+
+        update_document(&mut document, center, profiles);
+
+        let content = document.to_string();
+
+        // never write something that wouldn't load again
+        Config::parse(&content).context("refusing to save invalid config")?;
+
+        write_atomic(&self.path, content.as_bytes())
+            .with_context(|| format!("failed to save config {}", self.path.display()))
+    }
+}
+
+// f32 -> f64 would write 1.100000023841858 instead of 1.1
+fn float(f: f32) -> Item {
+    value(f.to_string().parse::<f64>().unwrap_or_default())
+}
+
+fn update_document(document: &mut DocumentMut, center: Option<EulerData>, profiles: &Profiles) {
+    match center {
+        Some(center) => {
+            if !document.contains_table("center") {
+                document["center"] = toml_edit::table();
+            }
+
+            let table = &mut document["center"];
+
+            table["roll"] = float(center.roll);
+            table["pitch"] = float(center.pitch);
+            table["yaw"] = float(center.yaw);
+        }
+        None => {
+            document.remove("center");
+        }
+    }
+
+    // automatic switches are temporary, the config keeps the user's choice
+    document["profile"] = value(profiles.selected());
+
+    let mut others = profiles
+        .iter()
+        .filter(|(name, _)| *name != DEFAULT_PROFILE)
+        .peekable();
+
+    if others.peek().is_none() {
+        document.remove("profiles");
+    } else {
+        if !document.contains_table("profiles") {
+            let mut table = toml_edit::Table::new();
+            table.set_implicit(true);
+
+            document["profiles"] = Item::Table(table);
+        }
+
+        let table = document["profiles"].as_table_mut().unwrap();
+        table.retain(|name, _| profiles.get(name).is_some() && name != DEFAULT_PROFILE);
+
+        for (name, settings) in others {
+            write_settings(table.entry(name).or_insert_with(toml_edit::table), settings);
+        }
+    }
+
+    write_settings(
+        document.entry("euler").or_insert_with(toml_edit::table),
+        &profiles.get(DEFAULT_PROFILE).unwrap_or_default(),
+    );
+}
+
+fn write_settings(item: &mut Item, settings: &EulerSettings) {
+    // all keys get set below, so a table in another form can just be replaced
+    if !item.is_table() {
+        *item = toml_edit::table();
+    }
+
+    item["roll_scale"] = float(settings.roll_scale);
+    item["pitch_scale"] = float(settings.pitch_scale);
+    item["yaw_scale"] = float(settings.yaw_scale);
+
+    item["roll_invert"] = value(settings.roll_invert);
+    item["pitch_invert"] = value(settings.pitch_invert);
+    item["yaw_invert"] = value(settings.yaw_invert);
+
+    item["curve"] = float(settings.curve);
+    item["smoothing"] = float(settings.smoothing);
+}
+
+/// Writes to a temporary file next to `path` and renames it over `path`
+fn write_atomic(path: &Path, content: &[u8]) -> Result<()> {
+    let Some(file_name) = path.file_name() else {
+        bail!("{} is not a file path", path.display());
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut temp_name = file_name.to_os_string();
+    temp_name.push(format!(".{}.tmp", process::id()));
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = (|| {
+        let mut file = fs::File::create(&temp_path)?;
+
+        if let Ok(metadata) = fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+
+        file.write_all(content)?;
+        file.sync_all()?;
+
+        fs::rename(&temp_path, path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    Ok(result?)
+}
+
+/// Recursively overlays `overlay` onto `base`, so a user file only needs the keys it changes
+fn merge_tables(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge_tables(base, overlay)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, env, fs, process};
+
+    use crate::{
+        control::endpoint::ControlEndpoint,
+        euler::{EulerData, EulerSettings},
+        open_track_data::PoseField,
+        profile::{Profiles, DEFAULT_PROFILE},
+        scheduler::OutputMode,
+    };
+
+    use super::{merge_tables, Config, ConfigWriter};
+
+    #[test]
+    fn example_config_is_valid() {
+        Config::parse(include_str!("../resources/config.toml")).unwrap();
+    }
+
+    #[test]
+    fn parse_config() {
+        let table = Config::parse(
+            r#"
+            target = "opentrack.local:5000"
+            rate = 120
+            output_mode = "hold"
+
+            [relay]
+            address = "0.0.0.0:4243"
+            fields = ["x", "z"]
+
+            [euler]
+            yaw_scale = 2.0
+            pitch_invert = true
+
+            [center]
+            roll = 0.0
+            pitch = 1.0
+            yaw = 2.0
+            "#,
+        )
+        .unwrap();
+
+        let config: Config = table.try_into().unwrap();
+
+        assert_eq!(config.target.to_string(), "opentrack.local:5000");
+        assert_eq!(config.rate, Some(120.0));
+        assert_eq!(config.output_mode, OutputMode::Hold);
+        assert_eq!(config.relay.fields, vec![PoseField::X, PoseField::Z]);
+        assert_eq!(config.euler.yaw_scale, 2.0);
+        assert_eq!(config.euler.roll_scale, 1.0);
+        assert!(config.euler.pitch_invert);
+        assert_eq!(config.center.map(|center| center.yaw), Some(2.0));
+    }
+
+    #[test]
+    fn errors_name_the_key() {
+        let err = Config::parse("[euler]\nyaw_scal = 2.0\n").unwrap_err();
+        assert!(format!("{err:#}").contains("yaw_scal"), "{err:#}");
+
+        let err = Config::parse("[euler]\nyaw_scale = \"fast\"\n").unwrap_err();
+        assert!(format!("{err:#}").contains("yaw_scale"), "{err:#}");
+
+        let err = Config::parse("rate = -5.0\n").unwrap_err();
+        assert!(format!("{err:#}").contains("`rate`"), "{err:#}");
+
+        let err = Config::parse("rate = 1e12\n").unwrap_err();
+        assert!(format!("{err:#}").contains("`rate`"), "{err:#}");
+
+        let err = Config::parse("[device]\nstall_timeout = 1e30\n").unwrap_err();
+        assert!(format!("{err:#}").contains("stall_timeout"), "{err:#}");
+
+        let err = Config::parse("[auto_profile]\ninterval = 1e30\n").unwrap_err();
+        assert!(
+            format!("{err:#}").contains("auto_profile.interval"),
+            "{err:#}"
+        );
+
+        let err = Config::parse("[device]\nprefer = \"model:two\"\n").unwrap_err();
+        assert!(format!("{err:#}").contains("prefer"), "{err:#}");
+
+        let err = Config::parse("log_level = \"info,relay=loud\"\n").unwrap_err();
+        assert!(format!("{err:#}").contains("`log_level`"), "{err:#}");
+
+        // only checked once all files are merged
+        let merged_err = |content: &str| {
+            let config: Config = Config::parse(content).unwrap().try_into().unwrap();
+            config.validate().unwrap_err()
+        };
+
+        let err = merged_err("[control]\ntcp = \"0.0.0.0:4244\"\n");
+        assert!(format!("{err:#}").contains("control.tcp"), "{err:#}");
+
+        let err = merged_err(
+            "[[auto_profile.rules]]\nprofile = \"racing\"\nexecutables = [\"acc.exe\"]\n",
+        );
+        assert!(
+            format!("{err:#}").contains("auto_profile.rules[0].profile"),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn clients_stay_on_the_socket() {
+        let mut config: Config =
+            Config::parse("[control]\nsocket = \"/tmp/control.sock\"\ntcp = \"127.0.0.1:4244\"\n")
+                .unwrap()
+                .try_into()
+                .unwrap();
+
+        // the daemon's bind address is no reason to leave the socket
+        assert_eq!(
+            config.control.endpoint(),
+            ControlEndpoint::Unix("/tmp/control.sock".into())
+        );
+
+        let address = "127.0.0.1:4245".parse().unwrap();
+        config.control.client_tcp = Some(address);
+        assert_eq!(config.control.endpoint(), ControlEndpoint::Tcp(address));
+    }
+
+    #[test]
+    fn user_overrides_system() {
+        let mut merged =
+            Config::parse("rate = 60\n[euler]\nyaw_scale = 2.0\nroll_scale = 3.0\n").unwrap();
+        merge_tables(
+            &mut merged,
+            Config::parse("[euler]\nyaw_scale = 4.0\n").unwrap(),
+        );
+
+        let config: Config = merged.try_into().unwrap();
+
+        assert_eq!(config.rate, Some(60.0));
+        assert_eq!(config.euler.yaw_scale, 4.0);
+        assert_eq!(config.euler.roll_scale, 3.0);
+
+        // a user file may use profiles defined in the system file
+        let mut merged = Config::parse("[profiles.racing]\nyaw_scale = 2.0\n").unwrap();
+        merge_tables(
+            &mut merged,
+            Config::parse("profile = \"racing\"\n").unwrap(),
+        );
+
+        let config: Config = merged.try_into().unwrap();
+
+        config.validate().unwrap();
+        assert_eq!(config.profile, "racing");
+    }
+
+    #[test]
+    fn save_path_without_user_config() {
+        let dir = env::temp_dir().join(format!("xr_to_opentrack_paths_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let system_path = dir.join("system.toml");
+        let user_path = dir.join("user.toml");
+        fs::write(&system_path, "").unwrap();
+
+        // users don't get pointed at the root owned system config
+        assert_eq!(
+            Config::pick_save_path(Some(user_path.clone()), system_path.clone(), false),
+            user_path
+        );
+        assert_eq!(
+            Config::pick_save_path(Some(user_path.clone()), system_path.clone(), true),
+            system_path
+        );
+        assert_eq!(
+            Config::pick_save_path(None, system_path.clone(), false),
+            system_path
+        );
+
+        // an existing user config always wins
+        fs::write(&user_path, "").unwrap();
+
+        assert_eq!(
+            Config::pick_save_path(Some(user_path.clone()), system_path, true),
+            user_path
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn save_keeps_other_keys() {
+        let path = env::temp_dir().join(format!("xr_to_opentrack_save_{}.toml", process::id()));
+
+        fs::write(
+            &path,
+            "# tuned for the living room\nrate = 90\n\n[euler]\nyaw_scale = 2.0 # wide\n",
+        )
+        .unwrap();
+
+        let center = Some(EulerData {
+            roll: 0.5,
+            pitch: 1.0,
+            yaw: 2.0,
+        });
+        let default = EulerSettings {
+            yaw_scale: 1.1,
+            pitch_invert: true,
+            ..Default::default()
+        };
+        let racing = EulerSettings {
+            curve: 1.5,
+            smoothing: 0.2,
+            ..Default::default()
+        };
+
+        let mut profiles = Profiles::new(DEFAULT_PROFILE, default, &BTreeMap::new()).unwrap();
+        profiles.create("racing").unwrap();
+        profiles.activate("racing").unwrap();
+        profiles.update_active(racing);
+
+        let writer = ConfigWriter::new(path.clone(), false);
+        writer.save(center, &profiles).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with("# tuned for the living room\nrate = 90\n"));
+        assert!(content.contains("yaw_scale = 1.1"), "{content}");
+
+        let config = Config::load(Some(&path)).unwrap();
+        assert_eq!(config.rate, Some(90.0));
+        assert_eq!(config.center, center);
+        assert_eq!(config.profiles().unwrap(), profiles);
+
+        // an automatic switch doesn't become the startup profile
+        profiles.switch(DEFAULT_PROFILE).unwrap();
+        writer.save(center, &profiles).unwrap();
+
+        assert_eq!(Config::load(Some(&path)).unwrap().profile, "racing");
+
+        // dropped center and profiles are removed from the file again
+        profiles.activate(DEFAULT_PROFILE).unwrap();
+        profiles.delete("racing").unwrap();
+        writer.save(None, &profiles).unwrap();
+
+        let config = Config::load(Some(&path)).unwrap();
+        assert_eq!(config.center, None);
+        assert!(config.profiles.is_empty());
+        assert_eq!(config.euler, default);
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,74 @@
+use std::{fs, os::unix::fs::PermissionsExt, path::Path};
+
+use anyhow::{bail, Context, Result};
+use log::warn;
+
+/// Pre-shared secret TCP clients have to present before their first request
+#[derive(Clone)]
+pub struct ControlToken(String);
+
+impl ControlToken {
+    const MIN_LENGTH: usize = 16;
+
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read control token {}", path.display()))?;
+
+        if fs::metadata(path)?.permissions().mode() & 0o077 != 0 {
+            warn!(
+                "control token {} is readable by other users",
+                path.display()
+            );
+        }
+
+        Self::new(content.trim())
+    }
+
+    fn new(token: &str) -> Result<Self> {
+        if token.len() < Self::MIN_LENGTH {
+            bail!(
+                "control token has to be at least {} characters long",
+                Self::MIN_LENGTH
+            );
+        }
+
+        Ok(Self(token.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Compares in constant time, so the token can't be guessed byte by byte
+    pub fn matches(&self, given: &str) -> bool {
+        let expected = self.0.as_bytes();
+        let given = given.as_bytes();
+
+        if expected.len() != given.len() {
+            return false;
+        }
+
+        expected
+            .iter()
+            .zip(given)
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ControlToken;
+
+    #[test]
+    fn token_matching() {
+        let token = ControlToken::new("0123456789abcdef").unwrap();
+
+        assert!(token.matches("0123456789abcdef"));
+        assert!(!token.matches("0123456789abcdeF"));
+        assert!(!token.matches("0123456789abcde"));
+        assert!(!token.matches(""));
+
+        assert!(ControlToken::new("too short").is_err());
+    }
+}
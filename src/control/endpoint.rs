@@ -0,0 +1,343 @@
+use std::{
+    env,
+    fs::{self, DirBuilder},
+    io::{self, Read, Write},
+    mem,
+    net::{SocketAddr, TcpStream},
+    os::{
+        fd::AsRawFd,
+        unix::{
+            fs::{DirBuilderExt, PermissionsExt},
+            net::{UnixListener, UnixStream},
+        },
+    },
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+
+const SOCKET_NAME: &str = "xr_to_opentrack.sock";
+
+/// Runtime directory of the system service
+const SYSTEM_DIR: &str = "/run";
+
+/// Not exported by the libc crate, the value is the same on all common architectures
+const SO_PEERGROUPS: libc::c_int = 59;
+
+/// Where the control server listens and clients connect to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlEndpoint {
+    Unix(PathBuf),
+    Tcp(SocketAddr),
+}
+
+impl ControlEndpoint {
+    /// `$XDG_RUNTIME_DIR` for user sessions, `/run` for the system service
+    pub fn default_socket_path() -> PathBuf {
+        match env::var_os("XDG_RUNTIME_DIR") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir).join(SOCKET_NAME),
+            _ => Path::new(SYSTEM_DIR).join(SOCKET_NAME),
+        }
+    }
+
+    /// The session daemon's socket if one is listening, otherwise the system service's
+    pub fn default_client_socket_path() -> PathBuf {
+        let path = Self::default_socket_path();
+
+        if path.exists() {
+            path
+        } else {
+            Path::new(SYSTEM_DIR).join(SOCKET_NAME)
+        }
+    }
+
+    pub fn connect(&self) -> Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)> {
+        match self {
+            ControlEndpoint::Unix(path) => {
+                let stream = UnixStream::connect(path)
+                    .with_context(|| format!("failed to connect to {}", path.display()))?;
+
+                Ok((Box::new(stream.try_clone()?), Box::new(stream)))
+            }
+            ControlEndpoint::Tcp(address) => {
+                let stream = TcpStream::connect(address)
+                    .with_context(|| format!("failed to connect to {address}"))?;
+
+                Ok((Box::new(stream.try_clone()?), Box::new(stream)))
+            }
+        }
+    }
+}
+
+/// Stream a control connection can run on
+pub trait Connection: Read + Write + Send + Sized + 'static {
+    fn try_clone(&self) -> io::Result<Self>;
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+
+    fn describe(&self) -> String;
+}
+
+impl Connection for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn describe(&self) -> String {
+        match self.peer_addr() {
+            Ok(address) => address.to_string(),
+            Err(_) => "unknown tcp peer".to_string(),
+        }
+    }
+}
+
+impl Connection for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn describe(&self) -> String {
+        match peer_credentials(self) {
+            Ok(cred) => format!("pid {} (uid {}, gid {})", cred.pid, cred.uid, cred.gid),
+            Err(_) => "unknown unix peer".to_string(),
+        }
+    }
+}
+
+/// Binds the control socket, replacing a stale socket file but never a live daemon's
+pub fn bind_unix(path: &Path) -> Result<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            bail!(
+                "another instance is already listening on {}",
+                path.display()
+            );
+        }
+
+        fs::remove_file(path)
+            .with_context(|| format!("failed to remove stale socket {}", path.display()))?;
+    }
+
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    fs::create_dir_all(parent)?;
+
+    // connecting needs write access, so this decides who may control the daemon: only its
+    // user, and for the system service also its group
+    let mode = if is_system_socket(path) { 0o660 } else { 0o600 };
+
+    // bind and chmod inside a private directory, the socket only becomes reachable once its
+    // permissions are set
+    let staging = parent.join(format!(".xr_to_opentrack.{}", process::id()));
+    let _ = fs::remove_dir_all(&staging);
+
+    DirBuilder::new()
+        .mode(0o700)
+        .create(&staging)
+        .with_context(|| format!("failed to create {}", staging.display()))?;
+
+    let staged_path = staging.join(SOCKET_NAME);
+
+    let result = UnixListener::bind(&staged_path)
+        .with_context(|| format!("failed to bind control socket {}", path.display()))
+        .and_then(|listener| {
+            fs::set_permissions(&staged_path, fs::Permissions::from_mode(mode))?;
+            fs::rename(&staged_path, path)
+                .with_context(|| format!("failed to move control socket to {}", path.display()))?;
+
+            Ok(listener)
+        });
+
+    let _ = fs::remove_dir_all(&staging);
+
+    result
+}
+
+fn is_system_socket(path: &Path) -> bool {
+    path.parent() == Some(Path::new(SYSTEM_DIR))
+}
+
+/// Who may connect to the control socket, checked on every connection on top of the socket's
+/// permissions: root, the daemon's user, and for the system socket members of its group
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerPolicy {
+    uid: libc::uid_t,
+    group: Option<libc::gid_t>,
+}
+
+impl PeerPolicy {
+    pub fn for_socket(path: &Path) -> Self {
+        Self {
+            uid: unsafe { libc::geteuid() },
+            group: is_system_socket(path).then(|| unsafe { libc::getegid() }),
+        }
+    }
+
+    pub fn check(&self, stream: &UnixStream) -> Result<()> {
+        let cred = peer_credentials(stream)?;
+
+        let mut groups = if self.group.is_some() {
+            peer_groups(stream)?
+        } else {
+            Vec::new()
+        };
+        groups.push(cred.gid);
+
+        if !self.allows(cred.uid, &groups) {
+            bail!(
+                "uid {} (pid {}) is not allowed to connect",
+                cred.uid,
+                cred.pid
+            );
+        }
+
+        Ok(())
+    }
+
+    fn allows(&self, uid: libc::uid_t, groups: &[libc::gid_t]) -> bool {
+        uid == 0 || uid == self.uid || self.group.is_some_and(|group| groups.contains(&group))
+    }
+}
+
+pub fn peer_credentials(stream: &UnixStream) -> io::Result<libc::ucred> {
+    let mut cred: libc::ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+    let res = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(cred)
+}
+
+/// Supplementary groups of the peer, as they were when it connected
+pub fn peer_groups(stream: &UnixStream) -> io::Result<Vec<libc::gid_t>> {
+    let mut groups: Vec<libc::gid_t> = vec![0; 64];
+
+    loop {
+        let mut len = (groups.len() * mem::size_of::<libc::gid_t>()) as libc::socklen_t;
+
+        let res = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                SO_PEERGROUPS,
+                groups.as_mut_ptr() as *mut libc::c_void,
+                &mut len,
+            )
+        };
+
+        let count = len as usize / mem::size_of::<libc::gid_t>();
+
+        if res == -1 {
+            let err = io::Error::last_os_error();
+
+            // the needed size got reported back
+            if err.raw_os_error() == Some(libc::ERANGE) && count > groups.len() {
+                groups.resize(count, 0);
+                continue;
+            }
+
+            return Err(err);
+        }
+
+        groups.truncate(count);
+
+        return Ok(groups);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        env, fs,
+        os::unix::{fs::PermissionsExt, net::UnixStream},
+        path::Path,
+        process,
+    };
+
+    use super::{bind_unix, peer_credentials, PeerPolicy};
+
+    #[test]
+    fn peer_permissions() {
+        // same user and root are always allowed
+        let user = PeerPolicy {
+            uid: 1000,
+            group: None,
+        };
+
+        assert!(user.allows(1000, &[1000]));
+        assert!(user.allows(0, &[0]));
+        assert!(!user.allows(1001, &[1000]));
+
+        // group members only for the system socket
+        let system = PeerPolicy {
+            uid: 0,
+            group: Some(50),
+        };
+
+        assert!(system.allows(1001, &[1001, 50]));
+        assert!(!system.allows(1001, &[1001, 51]));
+    }
+
+    #[test]
+    fn socket_permissions() {
+        let dir = env::temp_dir().join(format!("xr_socket_test_{}", process::id()));
+        let path = dir.join("control.sock");
+
+        let _listener = bind_unix(&path).unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // nothing of the staging is left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        UnixStream::connect(&path).unwrap();
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn own_credentials() {
+        let (a, _b) = UnixStream::pair().unwrap();
+        let cred = peer_credentials(&a).unwrap();
+
+        assert_eq!(cred.pid as u32, process::id());
+        assert_eq!(cred.uid, unsafe { libc::geteuid() });
+
+        // the own user is always allowed, with the system socket's group check too
+        PeerPolicy::for_socket(Path::new("/tmp/control.sock"))
+            .check(&a)
+            .unwrap();
+        PeerPolicy {
+            uid: unsafe { libc::geteuid() },
+            group: Some(unsafe { libc::getegid() }),
+        }
+        .check(&a)
+        .unwrap();
+    }
+}
@@ -0,0 +1,974 @@
+pub mod auth;
+pub mod endpoint;
+pub mod protocol;
+
+use std::{
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+use ring_channel::{RingReceiver, RingSender};
+use serde::Serialize;
+use serde_json::{from_str, to_value, Value};
+
+use crate::{
+    config::{Config, ConfigWriter},
+    euler::{EulerData, EulerHandler, EulerSettings},
+    logging::{self, LogFilter},
+    profile::Profiles,
+    status::{DaemonStatus, PoseUpdate},
+    supervisor::Supervisor,
+};
+use auth::ControlToken;
+use endpoint::{bind_unix, Connection, ControlEndpoint, PeerPolicy};
+use protocol::{
+    read_frame, read_line, write_frame, Request, RequestBody, Response, ResponseBody,
+    PROTOCOL_VERSION,
+};
+
+/// Pose subscriptions are accepted from this rate in Hz, faster ones are capped at the maximum
+const MIN_SUBSCRIPTION_RATE: f32 = 0.1;
+const MAX_SUBSCRIPTION_RATE: f32 = 250.0;
+
+/// Daemon side state that control requests operate on
+pub struct Controller {
+    euler_handler: EulerHandler,
+    handler_sender: RingSender<EulerHandler>,
+    euler_receiver: RingReceiver<EulerData>,
+
+    profiles: Profiles,
+    config_writer: ConfigWriter,
+    status: Arc<DaemonStatus>,
+}
+
+impl Controller {
+    pub fn new(
+        euler_handler: EulerHandler,
+        handler_sender: RingSender<EulerHandler>,
+        euler_receiver: RingReceiver<EulerData>,
+        profiles: Profiles,
+        config_writer: ConfigWriter,
+        status: Arc<DaemonStatus>,
+    ) -> Self {
+        Self {
+            euler_handler,
+            handler_sender,
+            euler_receiver,
+
+            profiles,
+            config_writer,
+            status,
+        }
+    }
+
+    pub fn handle(&mut self, body: RequestBody) -> ResponseBody {
+        match body {
+            RequestBody::Apply(commands) => {
+                let last_euler = self.euler_receiver.try_recv().ok();
+
+                debug!("received command: {commands:#?}");
+
+                if let Err(err) = self.euler_handler.apply_commands(commands, last_euler) {
+                    return ResponseBody::Error(err.to_string());
+                }
+
+                self.profiles
+                    .update_active(self.euler_handler.state().settings);
+
+                self.publish_handler()
+            }
+
+            RequestBody::GetConfig => Self::data(self.euler_handler.state()),
+
+            RequestBody::Status => Self::data(
+                self.status
+                    .snapshot(self.euler_handler.state(), self.profiles.active()),
+            ),
+
+            RequestBody::SaveConfig => self.save_config(),
+
+            RequestBody::Profiles => Self::data(self.profiles.list()),
+
+            RequestBody::ActivateProfile { name } => {
+                let result = self
+                    .profiles
+                    .activate(&name)
+                    .and_then(|settings| self.use_settings(settings));
+
+                self.profiles_changed(result)
+            }
+
+            RequestBody::CreateProfile { name } => {
+                let result = self.profiles.create(&name);
+                self.profiles_changed(result)
+            }
+
+            RequestBody::CloneProfile { source, name } => {
+                let result = self.profiles.clone_profile(&source, &name);
+                self.profiles_changed(result)
+            }
+
+            RequestBody::DeleteProfile { name } => {
+                let result = self.profiles.delete(&name);
+                self.profiles_changed(result)
+            }
+
+            RequestBody::LogLevel { filter } => {
+                if let Some(filter) = filter {
+                    match filter.parse::<LogFilter>() {
+                        Ok(filter) => {
+                            info!("log filter set to {filter}");
+                            logging::set_filter(filter);
+                        }
+                        Err(err) => return ResponseBody::Error(format!("{err:#}")),
+                    }
+                }
+
+                match logging::filter() {
+                    Some(filter) => Self::data(filter.to_string()),
+                    None => ResponseBody::Error("logging is not set up".to_string()),
+                }
+            }
+
+            RequestBody::Authenticate { .. } | RequestBody::Subscribe { .. } => {
+                ResponseBody::Error("request is handled by the connection".to_string())
+            }
+        }
+    }
+
+    /// Activates a profile without selecting it or saving the config, for switches the daemon
+    /// makes itself
+    pub fn switch_profile(&mut self, name: &str) -> Result<()> {
+        let settings = self.profiles.switch(name)?;
+
+        self.use_settings(settings)
+    }
+
+    fn use_settings(&mut self, settings: EulerSettings) -> Result<()> {
+        // the whole handler is swapped at once, so no pose mixes two profiles
+        self.euler_handler.set_settings(settings);
+
+        if self
+            .handler_sender
+            .send(self.euler_handler.clone())
+            .is_err()
+        {
+            bail!("output loop is not running");
+        }
+
+        Ok(())
+    }
+
+    /// Takes over center and profiles of a reloaded config. The active profile, the center and
+    /// each profile's settings only change if the config changed them, so runtime switches,
+    /// tuning and recentering survive unrelated edits.
+    pub fn reload(&mut self, old: &Config, new: &Config) -> Result<()> {
+        let mut profiles = new.profiles()?;
+        profiles.keep_unchanged(&old.profiles()?, &self.profiles);
+
+        if new.profile == old.profile {
+            if profiles.get(self.profiles.selected()).is_some() {
+                profiles.activate(self.profiles.selected())?;
+            }
+
+            if profiles.get(self.profiles.active()).is_some() {
+                profiles.switch(self.profiles.active())?;
+            }
+        }
+
+        if new.center != old.center {
+            self.euler_handler.set_reference(new.center);
+        }
+
+        self.euler_handler.set_settings(profiles.settings());
+        self.profiles = profiles;
+        self.config_writer.set_autosave(new.autosave);
+
+        // no autosave here, that would rewrite the file that was just edited
+        if self
+            .handler_sender
+            .send(self.euler_handler.clone())
+            .is_err()
+        {
+            bail!("output loop is not running");
+        }
+
+        Ok(())
+    }
+
+    /// Hands the changed handler to the output loop
+    fn publish_handler(&mut self) -> ResponseBody {
+        if self
+            .handler_sender
+            .send(self.euler_handler.clone())
+            .is_err()
+        {
+            return ResponseBody::Error("output loop is not running".to_string());
+        }
+
+        self.autosave()
+    }
+
+    fn profiles_changed(&mut self, result: Result<()>) -> ResponseBody {
+        match result {
+            Ok(()) => self.autosave(),
+            Err(err) => ResponseBody::Error(err.to_string()),
+        }
+    }
+
+    fn autosave(&self) -> ResponseBody {
+        if self.config_writer.autosave() {
+            if let ResponseBody::Error(reason) = self.save_config() {
+                return ResponseBody::Error(format!(
+                    "applied, but saving the config failed: {reason}"
+                ));
+            }
+        }
+
+        ResponseBody::Ok
+    }
+
+    fn save_config(&self) -> ResponseBody {
+        match self
+            .config_writer
+            .save(self.euler_handler.state().reference, &self.profiles)
+        {
+            Ok(()) => {
+                debug!("saved config to {}", self.config_writer.path().display());
+
+                ResponseBody::Ok
+            }
+            Err(err) => {
+                warn!("{err:#}");
+
+                ResponseBody::Error(format!("{err:#}"))
+            }
+        }
+    }
+
+    fn data(data: impl Serialize) -> ResponseBody {
+        match to_value(data) {
+            Ok(value) => ResponseBody::Data(value),
+            Err(err) => ResponseBody::Error(format!("failed to serialize answer: {err}")),
+        }
+    }
+}
+
+pub struct ControlServer;
+
+impl ControlServer {
+    /// Delay after a failed authentication, to slow down guessing
+    const AUTH_FAILURE_DELAY: Duration = Duration::from_secs(1);
+
+    /// Time from accepting a connection until its client has to be authenticated, so slow or
+    /// silent peers don't hold a connection slot
+    const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Connections waiting for authentication at once. Together with the failure delay this
+    /// bounds the guessing rate, however many connections a peer opens.
+    const MAX_UNAUTHENTICATED: usize = 4;
+
+    /// Failed accepts in a row (e.g. out of file descriptors) before the listener counts as
+    /// failed and gets restarted by the supervisor
+    const MAX_ACCEPT_ERRORS: u32 = 10;
+
+    /// Listens on the unix socket and, if requested, additionally on TCP. TCP clients have to
+    /// present the token first, if one is given. Binding TCP beyond loopback requires a token.
+    /// Returns the shared controller, for requests that don't come over a connection.
+    pub fn spawn(
+        controller: Controller,
+        socket_path: PathBuf,
+        tcp: Option<SocketAddr>,
+        token: Option<ControlToken>,
+        supervisor: &Supervisor,
+    ) -> Result<Arc<Mutex<Controller>>> {
+        if let Some(address) = tcp {
+            if !address.ip().is_loopback() && token.is_none() {
+                bail!("refusing to accept control connections on {address} without a token");
+            }
+        }
+
+        let controller = Arc::new(Mutex::new(controller));
+
+        let unix_listener = bind_unix(&socket_path)?;
+        let policy = PeerPolicy::for_socket(&socket_path);
+
+        debug!("control server listening on {}", socket_path.display());
+
+        supervisor.spawn("control server", {
+            let controller = controller.clone();
+
+            move || {
+                Self::serve(
+                    unix_listener.incoming(),
+                    &controller,
+                    |stream| policy.check(stream),
+                    &None,
+                )
+            }
+        });
+
+        if let Some(address) = tcp {
+            let tcp_listener = TcpListener::bind(address)
+                .with_context(|| format!("failed to bind control server to {address}"))?;
+
+            debug!(
+                "control server listening on tcp {address} ({})",
+                if token.is_some() {
+                    "token required"
+                } else {
+                    "no token"
+                }
+            );
+
+            supervisor.spawn("tcp control server", {
+                let controller = controller.clone();
+
+                move || Self::serve(tcp_listener.incoming(), &controller, |_| Ok(()), &token)
+            });
+        }
+
+        Ok(controller)
+    }
+
+    fn serve<C: Connection>(
+        incoming: impl Iterator<Item = io::Result<C>>,
+        controller: &Arc<Mutex<Controller>>,
+        authorize: impl Fn(&C) -> Result<()>,
+        token: &Option<ControlToken>,
+    ) -> Result<()> {
+        let mut accept_errors = 0;
+        let unauthenticated = Arc::new(AtomicUsize::new(0));
+
+        for stream in incoming {
+            match stream {
+                Ok(stream) => {
+                    accept_errors = 0;
+
+                    let accepted = Instant::now();
+
+                    if let Err(err) = authorize(&stream) {
+                        warn!(
+                            "rejected control connection from {}: {err:#}",
+                            stream.describe()
+                        );
+                        continue;
+                    }
+
+                    let pending = match token {
+                        Some(_) => match PendingAuth::try_new(&unauthenticated) {
+                            Some(pending) => Some(pending),
+                            None => {
+                                warn!(
+                                    "rejected control connection from {}: too many \
+                                     unauthenticated connections",
+                                    stream.describe()
+                                );
+                                continue;
+                            }
+                        },
+                        None => None,
+                    };
+
+                    let controller = controller.clone();
+                    let token = token.clone();
+
+                    thread::spawn(move || {
+                        if let Err(err) =
+                            Self::handle_connection(stream, controller, token, accepted, pending)
+                        {
+                            debug!("control connection error: {err:?}");
+                        }
+                    });
+                }
+                Err(err) => {
+                    debug!("control accept error: {err:?}");
+
+                    accept_errors += 1;
+
+                    if accept_errors >= Self::MAX_ACCEPT_ERRORS {
+                        return Err(err).context("accepting control connections keeps failing");
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_connection<C: Connection>(
+        stream: C,
+        controller: Arc<Mutex<Controller>>,
+        token: Option<ControlToken>,
+        accepted: Instant,
+        pending: Option<PendingAuth>,
+    ) -> Result<()> {
+        let peer = stream.describe();
+
+        debug!("incoming control connection from {peer}");
+
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = stream;
+
+        if let Some(token) = &token {
+            let deadline = accepted + Self::AUTH_TIMEOUT;
+
+            if !Self::authenticate(&mut reader, &mut writer, token, deadline, &peer)? {
+                return Ok(());
+            }
+
+            reader.get_ref().set_read_timeout(None)?;
+            drop(pending);
+        }
+
+        while let Some(line) = read_line(&mut reader)? {
+            let response = match from_str::<Request>(&line) {
+                Ok(request) if request.version != PROTOCOL_VERSION => {
+                    Response::new(request.id, Self::version_mismatch(request.version))
+                }
+                // already authenticated, or no token needed
+                Ok(Request {
+                    id,
+                    body: RequestBody::Authenticate { .. },
+                    ..
+                }) => Response::new(id, ResponseBody::Ok),
+                Ok(Request {
+                    id,
+                    body: RequestBody::Subscribe { rate },
+                    ..
+                }) if rate >= MIN_SUBSCRIPTION_RATE => {
+                    let status = controller.lock().unwrap().status.clone();
+
+                    return Self::stream_poses(reader, writer, id, rate, status);
+                }
+                Ok(Request {
+                    id,
+                    body: RequestBody::Subscribe { rate },
+                    ..
+                }) => Response::new(
+                    id,
+                    ResponseBody::Error(format!(
+                        "invalid subscription rate {rate}, has to be at least \
+                         {MIN_SUBSCRIPTION_RATE} Hz"
+                    )),
+                ),
+                Ok(request) => {
+                    Response::new(request.id, controller.lock().unwrap().handle(request.body))
+                }
+                Err(err) => {
+                    // answer with the id of the request, if at least that much could be parsed
+                    let id = from_str::<Value>(&line)
+                        .ok()
+                        .and_then(|value| value.get("id").and_then(Value::as_u64))
+                        .unwrap_or_default();
+
+                    Response::new(id, ResponseBody::Error(format!("malformed request: {err}")))
+                }
+            };
+
+            debug!("control response: {response:?}");
+
+            write_frame(&mut writer, &response)?;
+        }
+
+        Ok(())
+    }
+
+    /// Expects `Authenticate` with the right token as the first frame, before `deadline`.
+    /// Anything else gets answered with an error and ends the connection, so peers can't hold
+    /// on to an unauthenticated slot.
+    fn authenticate<C: Connection>(
+        reader: &mut BufReader<C>,
+        writer: &mut C,
+        token: &ControlToken,
+        deadline: Instant,
+        peer: &str,
+    ) -> Result<bool> {
+        let line = match read_line(&mut DeadlineReader { reader, deadline }) {
+            Ok(Some(line)) => line,
+            Ok(None) => return Ok(false),
+            Err(err) => {
+                warn!("control connection from {peer} didn't authenticate: {err:#}");
+                return Ok(false);
+            }
+        };
+
+        let (id, body) = match from_str::<Request>(&line) {
+            Ok(request) if request.version != PROTOCOL_VERSION => {
+                (request.id, Self::version_mismatch(request.version))
+            }
+            Ok(Request {
+                id,
+                body: RequestBody::Authenticate { token: given },
+                ..
+            }) => {
+                if token.matches(&given) {
+                    write_frame(writer, &Response::new(id, ResponseBody::Ok))?;
+
+                    return Ok(true);
+                }
+
+                warn!("failed control authentication from {peer}");
+
+                thread::sleep(Self::AUTH_FAILURE_DELAY);
+                (id, ResponseBody::Error("authentication failed".to_string()))
+            }
+            Ok(request) => {
+                warn!("unauthenticated control request from {peer}");
+
+                (
+                    request.id,
+                    ResponseBody::Error("authentication required".to_string()),
+                )
+            }
+            Err(err) => {
+                warn!("malformed control request from unauthenticated {peer}");
+
+                (0, ResponseBody::Error(format!("malformed request: {err}")))
+            }
+        };
+
+        write_frame(writer, &Response::new(id, body))?;
+
+        Ok(false)
+    }
+
+    fn version_mismatch(version: u32) -> ResponseBody {
+        ResponseBody::Error(format!(
+            "unsupported protocol version {version} (server speaks {PROTOCOL_VERSION})"
+        ))
+    }
+
+    /// Turns the connection into a pose stream, until the client hangs up. Waiting between
+    /// poses reads from the client, so a hang up is noticed also while no poses come in.
+    fn stream_poses<C: Connection>(
+        mut reader: BufReader<C>,
+        mut writer: C,
+        id: u64,
+        rate: f32,
+        status: Arc<DaemonStatus>,
+    ) -> Result<()> {
+        let period = Duration::try_from_secs_f32(1.0 / rate.min(MAX_SUBSCRIPTION_RATE))?;
+        let mut subscription = status.subscribe();
+
+        write_frame(&mut writer, &Response::new(id, ResponseBody::Ok))?;
+
+        let mut next_pose = Instant::now() + period;
+
+        loop {
+            let wait = next_pose.saturating_duration_since(Instant::now());
+
+            if !wait.is_zero() {
+                reader.get_ref().set_read_timeout(Some(wait))?;
+
+                match reader.fill_buf() {
+                    Ok([]) => {
+                        debug!("pose subscriber hung up");
+                        return Ok(());
+                    }
+                    // nothing is expected from the client anymore, just drop it
+                    Ok(data) => {
+                        let len = data.len();
+                        reader.consume(len);
+                        continue;
+                    }
+                    Err(err)
+                        if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+
+            next_pose = (next_pose + period).max(Instant::now());
+
+            if let Some(pose) = subscription.next_pose() {
+                write_frame(&mut writer, &pose)?;
+            }
+        }
+    }
+}
+
+/// Reads from a connection until `deadline`, however the peer spreads out its bytes
+struct DeadlineReader<'a, C: Connection> {
+    reader: &'a mut BufReader<C>,
+    deadline: Instant,
+}
+
+impl<C: Connection> Read for DeadlineReader<'_, C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let length = available.len().min(buf.len());
+
+        buf[..length].copy_from_slice(&available[..length]);
+        self.consume(length);
+
+        Ok(length)
+    }
+}
+
+impl<C: Connection> BufRead for DeadlineReader<'_, C> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+
+        if remaining.is_zero() {
+            return Err(io::Error::new(ErrorKind::TimedOut, "deadline passed"));
+        }
+
+        self.reader.get_ref().set_read_timeout(Some(remaining))?;
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.reader.consume(amount);
+    }
+}
+
+/// Slot of a connection that still has to authenticate, freed on drop
+struct PendingAuth(Arc<AtomicUsize>);
+
+impl PendingAuth {
+    fn try_new(count: &Arc<AtomicUsize>) -> Option<Self> {
+        count
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| {
+                (pending < ControlServer::MAX_UNAUTHENTICATED).then_some(pending + 1)
+            })
+            .ok()
+            .map(|_| Self(count.clone()))
+    }
+}
+
+impl Drop for PendingAuth {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub struct ControlClient {
+    reader: BufReader<Box<dyn Read + Send>>,
+    writer: Box<dyn Write + Send>,
+
+    next_id: u64,
+}
+
+impl ControlClient {
+    /// Connects and, if a token is given, authenticates right away
+    pub fn connect(endpoint: &ControlEndpoint, token: Option<&ControlToken>) -> Result<Self> {
+        let (reader, writer) = endpoint.connect()?;
+
+        let mut client = Self {
+            reader: BufReader::new(reader),
+            writer,
+
+            next_id: 1,
+        };
+
+        if let Some(token) = token {
+            let response = client.request(RequestBody::Authenticate {
+                token: token.as_str().to_string(),
+            })?;
+
+            if let ResponseBody::Error(reason) = response {
+                bail!("{reason}");
+            }
+        }
+
+        Ok(client)
+    }
+
+    pub fn request(&mut self, body: RequestBody) -> Result<ResponseBody> {
+        let id = self.send_request(body)?;
+
+        self.read_response(id)
+    }
+
+    /// Calls `on_pose` for every streamed pose, until the daemon closes the connection
+    pub fn subscribe(
+        &mut self,
+        rate: f32,
+        mut on_pose: impl FnMut(PoseUpdate) -> Result<()>,
+    ) -> Result<()> {
+        let id = self.send_request(RequestBody::Subscribe { rate })?;
+
+        match self.read_response(id)? {
+            ResponseBody::Ok => (),
+            ResponseBody::Error(reason) => bail!("{reason}"),
+            ResponseBody::Data(_) => bail!("unexpected answer to subscription"),
+        }
+
+        while let Some(pose) = read_frame(&mut self.reader)? {
+            on_pose(pose)?;
+        }
+
+        Ok(())
+    }
+
+    fn send_request(&mut self, body: RequestBody) -> Result<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        write_frame(
+            &mut self.writer,
+            &Request {
+                version: PROTOCOL_VERSION,
+                id,
+                body,
+            },
+        )?;
+
+        Ok(id)
+    }
+
+    fn read_response(&mut self, id: u64) -> Result<ResponseBody> {
+        let Some(response) = read_frame::<Response>(&mut self.reader)? else {
+            bail!("daemon closed the connection without answering");
+        };
+
+        if response.id != id {
+            bail!("received answer {} for request {id}", response.id);
+        }
+
+        Ok(response.body)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::BTreeMap,
+        env, fs,
+        io::{BufReader, Write},
+        num::NonZeroUsize,
+        os::unix::net::UnixStream,
+        process,
+        sync::Arc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use ring_channel::ring_channel;
+    use serde_json::from_value;
+
+    use crate::{
+        config::{Config, ConfigWriter},
+        euler::{EulerHandler, EulerSettings, EulerState},
+        profile::{ProfileList, Profiles, DEFAULT_PROFILE},
+        status::DaemonStatus,
+        supervisor::Supervisor,
+        Command,
+    };
+
+    use super::{
+        auth::ControlToken,
+        endpoint::ControlEndpoint,
+        protocol::{read_frame, write_frame, Request, RequestBody, Response, ResponseBody},
+        ControlClient, ControlServer, Controller, PROTOCOL_VERSION,
+    };
+
+    #[test]
+    fn control_round_trip() {
+        let socket_path =
+            env::temp_dir().join(format!("xr_to_opentrack_test_{}.sock", process::id()));
+        let config_path =
+            env::temp_dir().join(format!("xr_to_opentrack_test_{}.toml", process::id()));
+
+        let (handler_sender, handler_receiver) = ring_channel(NonZeroUsize::new(1).unwrap());
+        let (_euler_sender, euler_receiver) = ring_channel(NonZeroUsize::new(1).unwrap());
+        let status = Arc::new(DaemonStatus::new("127.0.0.1:4242".parse().unwrap(), None));
+
+        ControlServer::spawn(
+            Controller::new(
+                EulerHandler::new(EulerState::default()),
+                handler_sender,
+                euler_receiver,
+                Profiles::new(DEFAULT_PROFILE, EulerSettings::default(), &BTreeMap::new()).unwrap(),
+                ConfigWriter::new(config_path.clone(), false),
+                status,
+            ),
+            socket_path.clone(),
+            None,
+            None,
+            &Supervisor::new(),
+        )
+        .unwrap();
+
+        let mut client =
+            ControlClient::connect(&ControlEndpoint::Unix(socket_path.clone()), None).unwrap();
+
+        assert_eq!(
+            client
+                .request(RequestBody::Apply(vec![Command::ScaleYaw(2.0)]))
+                .unwrap(),
+            ResponseBody::Ok
+        );
+        assert!(handler_receiver.try_recv().is_ok());
+
+        // no imu data yet, so recentering has to be refused
+        assert!(matches!(
+            client.request(RequestBody::Apply(vec![Command::Recenter])),
+            Ok(ResponseBody::Error(_))
+        ));
+
+        let ResponseBody::Data(config) = client.request(RequestBody::GetConfig).unwrap() else {
+            panic!("expected config data");
+        };
+        let config: EulerState = from_value(config).unwrap();
+
+        assert_eq!(config.settings.yaw_scale, 2.0);
+
+        // switching to a fresh profile and back keeps the tuning of both
+        for body in [
+            RequestBody::CreateProfile {
+                name: "racing".to_string(),
+            },
+            RequestBody::ActivateProfile {
+                name: "racing".to_string(),
+            },
+            RequestBody::Apply(vec![Command::ScaleYaw(3.0)]),
+            RequestBody::ActivateProfile {
+                name: DEFAULT_PROFILE.to_string(),
+            },
+        ] {
+            assert_eq!(client.request(body).unwrap(), ResponseBody::Ok);
+        }
+
+        let ResponseBody::Data(list) = client.request(RequestBody::Profiles).unwrap() else {
+            panic!("expected profile list");
+        };
+        let list: ProfileList = from_value(list).unwrap();
+
+        assert_eq!(list.active, DEFAULT_PROFILE);
+        assert_eq!(list.profiles, vec![DEFAULT_PROFILE, "racing"]);
+
+        assert!(matches!(
+            client.request(RequestBody::DeleteProfile {
+                name: DEFAULT_PROFILE.to_string()
+            }),
+            Ok(ResponseBody::Error(_))
+        ));
+
+        assert!(matches!(
+            client.request(RequestBody::LogLevel {
+                filter: Some("relay=loud".to_string())
+            }),
+            Ok(ResponseBody::Error(_))
+        ));
+
+        assert_eq!(
+            client.request(RequestBody::SaveConfig).unwrap(),
+            ResponseBody::Ok
+        );
+
+        let saved = Config::load(Some(&config_path)).unwrap();
+        assert_eq!(saved.euler.yaw_scale, 2.0);
+        assert_eq!(saved.profiles["racing"].yaw_scale, 3.0);
+
+        fs::remove_file(config_path).unwrap();
+        fs::remove_file(socket_path).unwrap();
+    }
+
+    #[test]
+    fn subscriber_hang_up() {
+        let status = Arc::new(DaemonStatus::new("127.0.0.1:4242".parse().unwrap(), None));
+        let (server, client) = UnixStream::pair().unwrap();
+
+        // no poses come in, the stream has to end on the hang up alone
+        let streaming = thread::spawn({
+            let status = status.clone();
+
+            move || {
+                ControlServer::stream_poses(
+                    BufReader::new(server.try_clone().unwrap()),
+                    server,
+                    7,
+                    10.0,
+                    status,
+                )
+            }
+        });
+
+        let response: Response = read_frame(&mut BufReader::new(&client)).unwrap().unwrap();
+        assert_eq!(response.id, 7);
+
+        drop(client);
+        streaming.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn authentication_first() {
+        let token_path =
+            env::temp_dir().join(format!("xr_to_opentrack_test_{}.token", process::id()));
+        fs::write(&token_path, "0123456789abcdef").unwrap();
+        let token = ControlToken::from_file(&token_path).unwrap();
+        fs::remove_file(token_path).unwrap();
+
+        let authenticate = |server: &UnixStream, deadline: Duration| {
+            ControlServer::authenticate(
+                &mut BufReader::new(server.try_clone().unwrap()),
+                &mut server.try_clone().unwrap(),
+                &token,
+                Instant::now() + deadline,
+                "test peer",
+            )
+            .unwrap()
+        };
+
+        let request = |body| Request {
+            version: PROTOCOL_VERSION,
+            id: 1,
+            body,
+        };
+
+        let (server, mut client) = UnixStream::pair().unwrap();
+        write_frame(
+            &mut client,
+            &request(RequestBody::Authenticate {
+                token: "0123456789abcdef".to_string(),
+            }),
+        )
+        .unwrap();
+
+        assert!(authenticate(&server, Duration::from_secs(5)));
+
+        // the first frame has to authenticate, anything else ends the connection
+        let mut status = Vec::new();
+        write_frame(&mut status, &request(RequestBody::Status)).unwrap();
+
+        for first in [
+            b"junk\n".as_slice(),
+            b"{\"version\":2,\"id\":1}\n".as_slice(),
+            &status,
+        ] {
+            let (server, mut client) = UnixStream::pair().unwrap();
+            client.write_all(first).unwrap();
+
+            assert!(!authenticate(&server, Duration::from_secs(5)));
+
+            let response: Response = read_frame(&mut BufReader::new(&client)).unwrap().unwrap();
+            assert!(matches!(response.body, ResponseBody::Error(_)));
+        }
+
+        // trickling in bytes doesn't extend the deadline
+        let (server, mut client) = UnixStream::pair().unwrap();
+        let trickle = thread::spawn(move || {
+            while client.write_all(b" ").is_ok() {
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+
+        let start = Instant::now();
+
+        assert!(!authenticate(&server, Duration::from_millis(200)));
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        drop(server);
+        trickle.join().unwrap();
+    }
+}
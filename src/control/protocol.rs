@@ -0,0 +1,220 @@
+use std::{
+    fmt,
+    io::{BufRead, Read, Write},
+};
+
+use anyhow::{bail, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{from_str, to_string, Value};
+
+use crate::Command;
+
+/// Version of the control protocol, bumped on incompatible changes
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Longest accepted frame, so a peer can't make the other side buffer without end
+pub const MAX_FRAME_SIZE: u64 = 64 * 1024;
+
+/// Every request is answered by exactly one [`Response`] carrying the same id
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub version: u32,
+    pub id: u64,
+    pub body: RequestBody,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum RequestBody {
+    /// Has to be the first request on TCP connections, if the daemon requires a token
+    Authenticate { token: String },
+
+    /// Applies all commands, or none of them if any is invalid
+    Apply(Vec<Command>),
+
+    /// Answered with the center, scales and inverts of the running daemon
+    GetConfig,
+
+    /// Answered with a [`crate::status::Status`]
+    Status,
+
+    /// Writes the center and profiles to the daemon's config file
+    SaveConfig,
+
+    /// Answered with a [`crate::profile::ProfileList`]
+    Profiles,
+
+    /// Switches to the named profile, the center is kept
+    ActivateProfile { name: String },
+
+    /// Creates a profile with default settings
+    CreateProfile { name: String },
+
+    /// Creates the profile `name` as a copy of `source`
+    CloneProfile { source: String, name: String },
+
+    /// Deletes a profile, neither the active one nor `default`
+    DeleteProfile { name: String },
+
+    /// Answered with the log filter in effect, after replacing it if `filter` is given
+    LogLevel { filter: Option<String> },
+
+    /// Answered with `Ok`, followed by a [`crate::status::PoseUpdate`] frame per new pose, at
+    /// most `rate` times per second. The connection stays a pose stream until it is closed.
+    Subscribe { rate: f32 },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub version: u32,
+    pub id: u64,
+    pub body: ResponseBody,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ResponseBody {
+    Ok,
+    Error(String),
+    Data(Value),
+}
+
+impl Response {
+    pub fn new(id: u64, body: ResponseBody) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            id,
+            body,
+        }
+    }
+}
+
+impl fmt::Debug for RequestBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // keep the token out of debug logs
+            RequestBody::Authenticate { .. } => write!(f, "Authenticate {{ .. }}"),
+            RequestBody::Apply(commands) => f.debug_tuple("Apply").field(commands).finish(),
+            RequestBody::GetConfig => write!(f, "GetConfig"),
+            RequestBody::Status => write!(f, "Status"),
+            RequestBody::SaveConfig => write!(f, "SaveConfig"),
+            RequestBody::Profiles => write!(f, "Profiles"),
+            RequestBody::ActivateProfile { name } => f
+                .debug_struct("ActivateProfile")
+                .field("name", name)
+                .finish(),
+            RequestBody::CreateProfile { name } => {
+                f.debug_struct("CreateProfile").field("name", name).finish()
+            }
+            RequestBody::CloneProfile { source, name } => f
+                .debug_struct("CloneProfile")
+                .field("source", source)
+                .field("name", name)
+                .finish(),
+            RequestBody::DeleteProfile { name } => {
+                f.debug_struct("DeleteProfile").field("name", name).finish()
+            }
+            RequestBody::LogLevel { filter } => {
+                f.debug_struct("LogLevel").field("filter", filter).finish()
+            }
+            RequestBody::Subscribe { rate } => {
+                f.debug_struct("Subscribe").field("rate", rate).finish()
+            }
+        }
+    }
+}
+
+/// Frames are single lines of JSON
+pub fn write_frame(writer: &mut impl Write, frame: &impl Serialize) -> Result<()> {
+    let mut line = to_string(frame)?;
+    line.push('\n');
+
+    writer.write_all(line.as_bytes())?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Returns `None` once the other side closed the connection
+pub fn read_frame<T: DeserializeOwned>(reader: &mut impl BufRead) -> Result<Option<T>> {
+    match read_line(reader)? {
+        Some(line) => Ok(Some(from_str(&line)?)),
+        None => Ok(None),
+    }
+}
+
+/// Reads the next non-empty line, of at most [`MAX_FRAME_SIZE`] bytes
+pub fn read_line(reader: &mut impl BufRead) -> Result<Option<String>> {
+    loop {
+        let mut line = String::new();
+
+        let length = reader.by_ref().take(MAX_FRAME_SIZE).read_line(&mut line)?;
+
+        if length == 0 {
+            return Ok(None);
+        }
+
+        if !line.ends_with('\n') {
+            if length as u64 == MAX_FRAME_SIZE {
+                bail!("frame exceeds {MAX_FRAME_SIZE} bytes");
+            }
+
+            bail!("connection closed in the middle of a frame");
+        }
+
+        if !line.trim().is_empty() {
+            return Ok(Some(line));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::BufReader;
+
+    use crate::Command;
+
+    use super::{read_frame, write_frame, Request, RequestBody, MAX_FRAME_SIZE, PROTOCOL_VERSION};
+
+    #[test]
+    fn frame_round_trip() {
+        let mut buffer = Vec::new();
+
+        for id in 0..2 {
+            write_frame(
+                &mut buffer,
+                &Request {
+                    version: PROTOCOL_VERSION,
+                    id,
+                    body: RequestBody::Apply(vec![Command::Recenter, Command::ScaleYaw(2.0)]),
+                },
+            )
+            .unwrap();
+        }
+
+        let mut reader = BufReader::new(buffer.as_slice());
+
+        for id in 0..2 {
+            let request: Request = read_frame(&mut reader).unwrap().unwrap();
+            assert_eq!(request.id, id);
+        }
+
+        assert!(read_frame::<Request>(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_frame() {
+        let mut reader = BufReader::new(br#"{"version":1"#.as_slice());
+
+        assert!(read_frame::<Request>(&mut reader).is_err());
+    }
+
+    #[test]
+    fn oversized_frame() {
+        let line = vec![b' '; MAX_FRAME_SIZE as usize + 1];
+        let mut reader = BufReader::new(line.as_slice());
+
+        assert_eq!(
+            read_frame::<Request>(&mut reader).unwrap_err().to_string(),
+            format!("frame exceeds {MAX_FRAME_SIZE} bytes")
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use std::{fmt::Display, path::PathBuf, process::ExitCode};
+
+use rusb::Context;
+use serde_json::from_value;
+
+use crate::{
+    config::Config,
+    control::protocol::{RequestBody, ResponseBody},
+    hotplug::UsbDevice,
+    status::Status,
+};
+
+/// Checks everything the daemon depends on and prints one line per check. Fails if anything
+/// would keep the daemon from working.
+pub fn run(config: &Config, config_paths: &[PathBuf]) -> ExitCode {
+    let mut report = Report::default();
+
+    let loaded: Vec<String> = config_paths
+        .iter()
+        .filter(|path| path.exists())
+        .map(|path| path.display().to_string())
+        .collect();
+
+    if loaded.is_empty() {
+        report.ok("config", "no config file, using the defaults");
+    } else {
+        report.ok("config", format!("loaded {}", loaded.join(", ")));
+    }
+
+    // `has_hotplug` panics when libusb can't be initialized at all, e.g. without /dev/bus/usb
+    match Context::new() {
+        Ok(context) if rusb::has_hotplug() => {
+            report.ok("libusb", "hotplug supported");
+            check_glasses(&mut report, &context);
+        }
+        Ok(_) => report.fail(
+            "libusb",
+            "hotplug not supported, libusb probably needs an update",
+        ),
+        Err(err) => report.fail("libusb", format!("failed to initialize: {err}")),
+    }
+
+    check_daemon(&mut report, config);
+
+    match config.target.resolve() {
+        Ok(addresses) => {
+            let addresses: Vec<String> = addresses.iter().map(ToString::to_string).collect();
+
+            report.ok(
+                "target",
+                format!("{} resolves to {}", config.target, addresses.join(", ")),
+            );
+        }
+        Err(err) => report.fail("target", format!("{err:#}")),
+    }
+
+    if report.failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn check_glasses(report: &mut Report, context: &Context) {
+    let devices = match UsbDevice::list(context) {
+        Ok(devices) => devices,
+        Err(err) => {
+            report.fail("glasses", format!("failed to list usb devices: {err:#}"));
+            return;
+        }
+    };
+
+    let mut supported = 0;
+
+    for device in devices {
+        if device.model.is_none() {
+            report.warn("glasses", format!("{device}, please report this product"));
+            continue;
+        }
+
+        supported += 1;
+
+        // the sdk opens the device itself, this only finds missing permissions early
+        match &device.open_error {
+            None => report.ok("glasses", &device),
+            Some(_) => report.fail("glasses", format!("{device}, is a udev rule missing?")),
+        }
+    }
+
+    if supported == 0 {
+        report.fail("glasses", "no supported glasses connected");
+    }
+}
+
+fn check_daemon(report: &mut Report, config: &Config) {
+    let response = config
+        .control
+        .connect()
+        .and_then(|mut client| client.request(RequestBody::Status));
+
+    match response {
+        Ok(ResponseBody::Data(data)) => match from_value::<Status>(data) {
+            Ok(status) => match status.device {
+                Some(device) if status.stream.failed => report.fail(
+                    "daemon",
+                    format!(
+                        "running, but {} stopped streaming and didn't recover, replug it",
+                        device.model
+                    ),
+                ),
+                Some(device) => report.ok("daemon", format!("running, using {}", device.model)),
+                None if status.ambiguous => report.warn(
+                    "daemon",
+                    "running, but not streaming while several glasses are attached, unplug all \
+                     but one",
+                ),
+                None => report.ok("daemon", "running, waiting for glasses"),
+            },
+            Err(err) => report.fail("daemon", format!("unexpected status answer: {err}")),
+        },
+        Ok(ResponseBody::Error(reason)) => report.fail("daemon", reason),
+        Ok(ResponseBody::Ok) => report.fail("daemon", "answered without status"),
+        // not an error, doctor is also run before starting the daemon
+        Err(err) => report.warn("daemon", format!("not running ({err:#})")),
+    }
+}
+
+#[derive(Default)]
+struct Report {
+    failed: bool,
+}
+
+impl Report {
+    fn ok(&mut self, check: &str, detail: impl Display) {
+        println!("ok    {check}: {detail}");
+    }
+
+    fn warn(&mut self, check: &str, detail: impl Display) {
+        println!("warn  {check}: {detail}");
+    }
+
+    fn fail(&mut self, check: &str, detail: impl Display) {
+        self.failed = true;
+        println!("FAIL  {check}: {detail}");
+    }
+}
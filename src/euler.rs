@@ -1,8 +1,13 @@
-use std::ops::Sub;
+use std::{ops::Sub, time::Instant};
+
+use anyhow::{bail, Result};
+use log::debug;
+use serde::{Deserialize, Serialize};
 
 use crate::Command;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct EulerData {
     pub roll: f32,
     pub pitch: f32,
@@ -33,6 +38,29 @@ impl EulerData {
     pub fn invert_yaw(&mut self) {
         self.yaw = -self.yaw;
     }
+
+    /// `self` at 0, `to` at 1, each angle along its shortest arc
+    pub fn interpolate(self, to: EulerData, t: f32) -> EulerData {
+        EulerData {
+            roll: interpolate_angle(self.roll, to.roll, t),
+            pitch: interpolate_angle(self.pitch, to.pitch, t),
+            yaw: interpolate_angle(self.yaw, to.yaw, t),
+        }
+    }
+}
+
+/// Interpolates along the shortest arc, so crossing ±180° doesn't sweep the whole circle
+pub fn interpolate_angle(from: f32, to: f32, t: f32) -> f32 {
+    let delta = (to - from + 180.0).rem_euclid(360.0) - 180.0;
+    let angle = from + delta * t;
+
+    if angle > 180.0 {
+        angle - 360.0
+    } else if angle <= -180.0 {
+        angle + 360.0
+    } else {
+        angle
+    }
 }
 
 impl Sub for EulerData {
@@ -47,27 +75,44 @@ impl Sub for EulerData {
     }
 }
 
-pub struct EulerHandler {
-    debug: bool,
+/// Pose as reported by the glasses, stamped when the IMU callback fired
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImuSample {
+    pub euler: EulerData,
+    pub timestamp: Instant,
+}
 
-    reference: Option<EulerData>,
+impl ImuSample {
+    pub fn new(euler: EulerData) -> Self {
+        Self {
+            euler,
+            timestamp: Instant::now(),
+        }
+    }
+}
 
-    roll_scale: f32,
-    pitch_scale: f32,
-    yaw_scale: f32,
+/// Tunable part of the [`EulerHandler`]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EulerSettings {
+    pub roll_scale: f32,
+    pub pitch_scale: f32,
+    pub yaw_scale: f32,
 
-    roll_invert: bool,
-    pitch_invert: bool,
-    yaw_invert: bool,
-}
+    pub roll_invert: bool,
+    pub pitch_invert: bool,
+    pub yaw_invert: bool,
 
-impl EulerHandler {
-    pub fn new(debug: bool) -> Self {
-        Self {
-            debug,
+    /// Response curve exponent, above 1 small movements get damped, below 1 amplified
+    pub curve: f32,
 
-            reference: None,
+    /// Exponential smoothing factor in `[0, 1)`, 0 disables smoothing
+    pub smoothing: f32,
+}
 
+impl Default for EulerSettings {
+    fn default() -> Self {
+        Self {
             roll_scale: 1.0,
             pitch_scale: 1.0,
             yaw_scale: 1.0,
@@ -75,12 +120,150 @@ impl EulerHandler {
             roll_invert: false,
             pitch_invert: false,
             yaw_invert: false,
+
+            curve: 1.0,
+            smoothing: 0.0,
         }
     }
+}
+
+impl EulerSettings {
+    /// Errors name the offending key inside `table`
+    pub fn validate(&self, table: &str) -> Result<()> {
+        for (key, scale) in [
+            ("roll_scale", self.roll_scale),
+            ("pitch_scale", self.pitch_scale),
+            ("yaw_scale", self.yaw_scale),
+        ] {
+            if !scale.is_finite() {
+                bail!("key `{table}.{key}`: has to be a finite number (got {scale})");
+            }
+        }
+
+        if !valid_curve(self.curve) {
+            bail!(
+                "key `{table}.curve`: has to be a positive number (got {})",
+                self.curve
+            );
+        }
 
-    pub fn apply_commands(&mut self, commands: Vec<Command>, euler: Option<EulerData>) {
-        if self.debug {
-            println!("apply command: {commands:#?}");
+        if !valid_smoothing(self.smoothing) {
+            bail!(
+                "key `{table}.smoothing`: has to be at least 0 and below 1 (got {})",
+                self.smoothing
+            );
+        }
+
+        Ok(())
+    }
+}
+
+fn valid_curve(curve: f32) -> bool {
+    curve.is_finite() && curve > 0.0
+}
+
+fn valid_smoothing(smoothing: f32) -> bool {
+    (0.0..1.0).contains(&smoothing)
+}
+
+/// Maps `angle` onto the response curve, keeping ±180° in place
+fn apply_curve(angle: f32, curve: f32) -> f32 {
+    if curve == 1.0 {
+        return angle;
+    }
+
+    angle.signum() * 180.0 * (angle.abs() / 180.0).powf(curve)
+}
+
+/// Snapshot of the [`EulerHandler`], as reported over the control channel
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct EulerState {
+    pub reference: Option<EulerData>,
+    pub settings: EulerSettings,
+}
+
+#[derive(Clone)]
+pub struct EulerHandler {
+    reference: Option<EulerData>,
+    settings: EulerSettings,
+
+    /// Last smoothed pose as reported by the glasses, the state of the smoothing filter
+    smoothed: Option<EulerData>,
+}
+
+impl EulerHandler {
+    pub fn new(state: EulerState) -> Self {
+        Self {
+            reference: state.reference,
+            settings: state.settings,
+
+            smoothed: None,
+        }
+    }
+
+    /// Takes over center and settings of `other` in one go. The smoothing state is kept, so
+    /// the output continues from where it was.
+    pub fn update(&mut self, other: EulerHandler) {
+        self.reference = other.reference;
+        self.settings = other.settings;
+    }
+
+    pub fn set_reference(&mut self, reference: Option<EulerData>) {
+        self.reference = reference;
+
+        debug!("new center: {:?}", self.reference);
+    }
+
+    /// Replaces all settings at once, e.g. when switching profiles
+    pub fn set_settings(&mut self, settings: EulerSettings) {
+        self.settings = settings;
+
+        debug!("new settings: {:?}", self.settings);
+    }
+
+    pub fn state(&self) -> EulerState {
+        EulerState {
+            reference: self.reference,
+            settings: self.settings,
+        }
+    }
+
+    /// Applies all commands, or none of them if any is invalid
+    pub fn apply_commands(
+        &mut self,
+        commands: Vec<Command>,
+        euler: Option<EulerData>,
+    ) -> Result<()> {
+        debug!("apply command: {commands:#?}");
+
+        for command in &commands {
+            match command {
+                Command::Recenter => {
+                    if euler.is_none() {
+                        bail!("can't recenter without imu data (are the glasses connected?)");
+                    }
+                }
+
+                Command::ScalePitch(f) | Command::ScaleRoll(f) | Command::ScaleYaw(f) => {
+                    if !f.is_finite() {
+                        bail!("invalid scale {f} in {command:?}");
+                    }
+                }
+
+                Command::Curve(f) => {
+                    if !valid_curve(*f) {
+                        bail!("invalid curve {f}, has to be a positive number");
+                    }
+                }
+
+                Command::Smoothing(f) => {
+                    if !valid_smoothing(*f) {
+                        bail!("invalid smoothing {f}, has to be at least 0 and below 1");
+                    }
+                }
+
+                Command::InvertPitch(_) | Command::InvertRoll(_) | Command::InvertYaw(_) => (),
+            }
         }
 
         for command in commands {
@@ -88,76 +271,89 @@ impl EulerHandler {
                 Command::Recenter => {
                     self.reference = euler;
 
-                    if self.debug {
-                        println!("new center: {:?}", self.reference);
-                    }
+                    debug!("new center: {:?}", self.reference);
                 }
 
                 Command::ScalePitch(f) => {
-                    self.pitch_scale = f;
+                    self.settings.pitch_scale = f;
 
-                    if self.debug {
-                        println!("new pitch scale: {:?}", self.pitch_scale);
-                    }
+                    debug!("new pitch scale: {:?}", self.settings.pitch_scale);
                 }
                 Command::ScaleRoll(f) => {
-                    self.roll_scale = f;
+                    self.settings.roll_scale = f;
 
-                    if self.debug {
-                        println!("new roll scale: {:?}", self.roll_scale);
-                    }
+                    debug!("new roll scale: {:?}", self.settings.roll_scale);
                 }
                 Command::ScaleYaw(f) => {
-                    self.yaw_scale = f;
+                    self.settings.yaw_scale = f;
 
-                    if self.debug {
-                        println!("new yaw scale: {:?}", self.yaw_scale);
-                    }
+                    debug!("new yaw scale: {:?}", self.settings.yaw_scale);
                 }
 
                 Command::InvertPitch(i) => {
-                    self.pitch_invert = i;
+                    self.settings.pitch_invert = i;
 
-                    if self.debug {
-                        println!("new pitch invert: {}", self.pitch_invert);
-                    }
+                    debug!("new pitch invert: {}", self.settings.pitch_invert);
                 }
                 Command::InvertRoll(i) => {
-                    self.roll_invert = i;
+                    self.settings.roll_invert = i;
 
-                    if self.debug {
-                        println!("new roll invert: {}", self.roll_invert);
-                    }
+                    debug!("new roll invert: {}", self.settings.roll_invert);
                 }
                 Command::InvertYaw(i) => {
-                    self.yaw_invert = i;
+                    self.settings.yaw_invert = i;
 
-                    if self.debug {
-                        println!("new yaw invert: {}", self.yaw_invert);
-                    }
+                    debug!("new yaw invert: {}", self.settings.yaw_invert);
+                }
+
+                Command::Curve(f) => {
+                    self.settings.curve = f;
+
+                    debug!("new curve: {}", self.settings.curve);
+                }
+                Command::Smoothing(f) => {
+                    self.settings.smoothing = f;
+
+                    debug!("new smoothing: {}", self.settings.smoothing);
                 }
             }
         }
+
+        Ok(())
     }
 
-    pub fn apply_config(&self, mut euler: EulerData) -> EulerData {
+    pub fn apply_config(&mut self, mut euler: EulerData) -> EulerData {
+        // smooth the raw angles, they stay within ±180° so the shortest arc is the right one.
+        // Scaled angles may legitimately go beyond that.
+        if self.settings.smoothing > 0.0 {
+            if let Some(smoothed) = self.smoothed {
+                euler = smoothed.interpolate(euler, 1.0 - self.settings.smoothing);
+            }
+        }
+
+        self.smoothed = Some(euler);
+
         if let Some(reference) = self.reference {
             euler = euler - reference;
         }
 
-        euler.scale_pitch(self.pitch_scale);
-        euler.scale_roll(self.roll_scale);
-        euler.scale_yaw(self.yaw_scale);
+        euler.roll = apply_curve(euler.roll, self.settings.curve);
+        euler.pitch = apply_curve(euler.pitch, self.settings.curve);
+        euler.yaw = apply_curve(euler.yaw, self.settings.curve);
 
-        if self.pitch_invert {
+        euler.scale_pitch(self.settings.pitch_scale);
+        euler.scale_roll(self.settings.roll_scale);
+        euler.scale_yaw(self.settings.yaw_scale);
+
+        if self.settings.pitch_invert {
             euler.invert_pitch();
         }
 
-        if self.roll_invert {
+        if self.settings.roll_invert {
             euler.invert_roll();
         }
 
-        if self.yaw_invert {
+        if self.settings.yaw_invert {
             euler.invert_yaw();
         }
 
@@ -169,11 +365,11 @@ impl EulerHandler {
 mod test {
     use crate::Command;
 
-    use super::{EulerData, EulerHandler};
+    use super::{EulerData, EulerHandler, EulerSettings, EulerState};
 
     #[test]
     fn euler_center() {
-        let mut euler_handler = EulerHandler::new(false);
+        let mut euler_handler = EulerHandler::new(EulerState::default());
 
         let reference_euler = EulerData {
             roll: 10.0,
@@ -181,7 +377,9 @@ mod test {
             yaw: 10.0,
         };
 
-        euler_handler.apply_commands(vec![Command::Recenter], Some(reference_euler));
+        euler_handler
+            .apply_commands(vec![Command::Recenter], Some(reference_euler))
+            .unwrap();
 
         let test_euler = EulerData {
             roll: 5.0,
@@ -201,17 +399,19 @@ mod test {
 
     #[test]
     fn euler_scale_invert() {
-        let mut euler_handler = EulerHandler::new(false);
-
-        euler_handler.apply_commands(
-            vec![
-                Command::ScaleYaw(10.0),
-                Command::ScaleRoll(20.0),
-                Command::ScalePitch(30.0),
-                Command::InvertPitch(true),
-            ],
-            None,
-        );
+        let mut euler_handler = EulerHandler::new(EulerState::default());
+
+        euler_handler
+            .apply_commands(
+                vec![
+                    Command::ScaleYaw(10.0),
+                    Command::ScaleRoll(20.0),
+                    Command::ScalePitch(30.0),
+                    Command::InvertPitch(true),
+                ],
+                None,
+            )
+            .unwrap();
 
         let test_euler = EulerData {
             roll: 2.0,
@@ -228,4 +428,136 @@ mod test {
             }
         );
     }
+
+    #[test]
+    fn euler_invalid_commands() {
+        let mut euler_handler = EulerHandler::new(EulerState::default());
+
+        assert!(euler_handler
+            .apply_commands(
+                vec![Command::ScaleYaw(2.0), Command::ScaleRoll(f32::NAN)],
+                None
+            )
+            .is_err());
+        assert!(euler_handler
+            .apply_commands(vec![Command::Curve(0.0)], None)
+            .is_err());
+        assert!(euler_handler
+            .apply_commands(vec![Command::Smoothing(1.0)], None)
+            .is_err());
+        assert!(euler_handler
+            .apply_commands(vec![Command::Recenter], None)
+            .is_err());
+
+        // nothing of a rejected batch got applied
+        let test_euler = EulerData {
+            roll: 1.0,
+            pitch: 1.0,
+            yaw: 1.0,
+        };
+
+        assert_eq!(euler_handler.apply_config(test_euler), test_euler);
+    }
+
+    #[test]
+    fn euler_curve_smoothing() {
+        let mut euler_handler = EulerHandler::new(EulerState::default());
+
+        euler_handler
+            .apply_commands(vec![Command::Curve(2.0)], None)
+            .unwrap();
+
+        let curved = euler_handler.apply_config(EulerData {
+            roll: 90.0,
+            pitch: -90.0,
+            yaw: 180.0,
+        });
+
+        assert_eq!(
+            curved,
+            EulerData {
+                roll: 45.0,
+                pitch: -45.0,
+                yaw: 180.0
+            }
+        );
+
+        let mut euler_handler = EulerHandler::new(EulerState::default());
+
+        euler_handler
+            .apply_commands(vec![Command::Smoothing(0.75)], None)
+            .unwrap();
+
+        let zero = EulerData {
+            roll: 0.0,
+            pitch: 0.0,
+            yaw: 0.0,
+        };
+        let step = EulerData {
+            roll: 8.0,
+            pitch: 8.0,
+            yaw: 8.0,
+        };
+
+        assert_eq!(euler_handler.apply_config(zero), zero);
+        assert_eq!(euler_handler.apply_config(step).yaw, 2.0);
+
+        // new settings keep the filter state, so the output doesn't jump
+        let mut update = euler_handler.clone();
+        update.set_settings(EulerSettings {
+            smoothing: 0.5,
+            ..Default::default()
+        });
+        euler_handler.update(update);
+
+        assert_eq!(euler_handler.apply_config(step).yaw, 5.0);
+
+        // turning across ±180° doesn't swing through 0
+        let mut euler_handler = EulerHandler::new(EulerState::default());
+
+        euler_handler
+            .apply_commands(vec![Command::Smoothing(0.5)], None)
+            .unwrap();
+
+        let turn = |yaw| EulerData {
+            roll: 0.0,
+            pitch: 0.0,
+            yaw,
+        };
+
+        euler_handler.apply_config(turn(170.0));
+        assert_eq!(euler_handler.apply_config(turn(-170.0)).yaw, 180.0);
+        assert_eq!(euler_handler.apply_config(turn(-170.0)).yaw, -175.0);
+    }
+
+    #[test]
+    fn euler_scale_beyond_half_turn() {
+        let mut euler_handler = EulerHandler::new(EulerState::default());
+
+        euler_handler
+            .apply_commands(vec![Command::ScaleYaw(2.0)], None)
+            .unwrap();
+
+        let head = EulerData {
+            roll: 0.0,
+            pitch: 0.0,
+            yaw: 100.0,
+        };
+
+        // without smoothing every sample maps the same, the output isn't wrapped
+        assert_eq!(euler_handler.apply_config(head).yaw, 200.0);
+        assert_eq!(euler_handler.apply_config(head).yaw, 200.0);
+
+        // nor with smoothing
+        euler_handler
+            .apply_commands(vec![Command::Smoothing(0.5)], None)
+            .unwrap();
+
+        assert_eq!(euler_handler.apply_config(head).yaw, 200.0);
+
+        let further = EulerData { yaw: 110.0, ..head };
+
+        assert_eq!(euler_handler.apply_config(further).yaw, 210.0);
+        assert_eq!(euler_handler.apply_config(further).yaw, 215.0);
+    }
 }
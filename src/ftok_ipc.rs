@@ -108,7 +108,7 @@ mod test {
                 framenumber,
             );
 
-            let _ = socket.send(&ot_data.into_raw());
+            let _ = socket.send(&ot_data.encode());
 
             framenumber += 1;
 
@@ -0,0 +1,338 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, bail, Result};
+use rusb::{Device, UsbContext};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{device_id, viture_hotplug::VitureHotPlugHandler, DeviceKey, VitureModel};
+
+/// Viture device on the bus, as listed by `devices` and in the status
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsbDevice {
+    pub product_id: u16,
+
+    /// `None` for products this tool doesn't know (yet)
+    pub model: Option<VitureModel>,
+
+    pub bus: u8,
+
+    /// Address on the bus, unique while the device stays attached
+    #[serde(default)]
+    pub address: u8,
+
+    /// Port numbers from the root hub down, `[3, 2]` is port `3.2`
+    pub ports: Vec<u8>,
+
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+    pub serial: Option<String>,
+
+    /// Why the USB strings are missing, usually a lack of permissions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open_error: Option<String>,
+}
+
+impl UsbDevice {
+    /// All Viture devices on the bus, including unknown products
+    pub fn list<T: UsbContext>(context: &T) -> Result<Vec<Self>> {
+        Self::refresh(context, &[])
+    }
+
+    /// Like `list`, but devices in `known` are taken from there instead of being opened again,
+    /// which would get in the way of the SDK streaming from one of them
+    pub fn refresh<T: UsbContext>(context: &T, known: &[Self]) -> Result<Vec<Self>> {
+        Ok(context
+            .devices()?
+            .iter()
+            .filter_map(|device| Self::read(&device, known))
+            .collect())
+    }
+
+    fn read<T: UsbContext>(device: &Device<T>, known: &[Self]) -> Option<Self> {
+        let descriptor = device.device_descriptor().ok()?;
+
+        if descriptor.vendor_id() != VitureHotPlugHandler::VITURE_ID_VENDOR {
+            return None;
+        }
+
+        let mut usb_device = Self {
+            product_id: descriptor.product_id(),
+            model: VitureHotPlugHandler::model(descriptor.product_id()),
+
+            bus: device.bus_number(),
+            address: device.address(),
+            ports: device.port_numbers().unwrap_or_default(),
+
+            manufacturer: None,
+            product: None,
+            serial: None,
+
+            open_error: None,
+        };
+
+        if let Some(known) = known.iter().find(|known| known.key() == usb_device.key()) {
+            return Some(known.clone());
+        }
+
+        // the strings need an open handle, which needs permissions the listing doesn't
+        match device.open() {
+            Ok(handle) => {
+                usb_device.manufacturer = handle.read_manufacturer_string_ascii(&descriptor).ok();
+                usb_device.product = handle.read_product_string_ascii(&descriptor).ok();
+                usb_device.serial = handle.read_serial_number_string_ascii(&descriptor).ok();
+            }
+            Err(err) => usb_device.open_error = Some(err.to_string()),
+        }
+
+        Some(usb_device)
+    }
+
+    pub fn key(&self) -> DeviceKey {
+        DeviceKey {
+            bus: self.bus,
+            address: self.address,
+            product_id: self.product_id,
+        }
+    }
+
+    /// `bus-port.port`, as in sysfs and the kernel log
+    pub fn port_path(&self) -> String {
+        let ports: Vec<String> = self.ports.iter().map(ToString::to_string).collect();
+
+        format!("{}-{}", self.bus, ports.join("."))
+    }
+}
+
+impl fmt::Display for UsbDevice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.model {
+            Some(model) => write!(f, "{model}")?,
+            None => write!(f, "unknown model")?,
+        }
+
+        write!(
+            f,
+            " ({}) at {}",
+            device_id(self.product_id),
+            self.port_path()
+        )?;
+
+        let names: Vec<&str> = [&self.manufacturer, &self.product]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+
+        if !names.is_empty() {
+            write!(f, ", \"{}\"", names.join(" "))?;
+        }
+
+        if let Some(serial) = &self.serial {
+            write!(f, ", serial {serial}")?;
+        }
+
+        if let Some(err) = &self.open_error {
+            write!(f, ", usb strings unavailable: {err}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Glasses meant to drive the output: the supported ones matching the preference, without one
+/// the first supported ones
+pub fn select<'a>(
+    devices: &'a [UsbDevice],
+    preference: Option<&DevicePreference>,
+) -> Option<&'a UsbDevice> {
+    let mut supported = devices.iter().filter(|device| device.model.is_some());
+
+    match preference {
+        Some(preference) => supported.find(|device| preference.matches(device)),
+        None => supported.next(),
+    }
+}
+
+/// Which glasses drive the output, given as `serial:<serial>`, `port:<bus-port>` or
+/// `model:<one|one_lite|pro>`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DevicePreference {
+    Serial(String),
+    Port(String),
+    Model(VitureModel),
+}
+
+impl DevicePreference {
+    pub fn matches(&self, device: &UsbDevice) -> bool {
+        match self {
+            DevicePreference::Serial(serial) => device.serial.as_ref() == Some(serial),
+            DevicePreference::Port(port) => device.port_path() == *port,
+            DevicePreference::Model(model) => device.model == Some(*model),
+        }
+    }
+
+    const MODELS: [(&str, VitureModel); 3] = [
+        ("one", VitureModel::One),
+        ("one_lite", VitureModel::OneLite),
+        ("pro", VitureModel::Pro),
+    ];
+}
+
+impl FromStr for DevicePreference {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, value) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("expected `serial:`, `port:` or `model:` in {s:?}"))?;
+
+        if value.is_empty() {
+            bail!("missing value in {s:?}");
+        }
+
+        Ok(match kind {
+            "serial" => DevicePreference::Serial(value.to_string()),
+            "port" => DevicePreference::Port(value.to_string()),
+            "model" => DevicePreference::Model(
+                Self::MODELS
+                    .iter()
+                    .find(|(name, _)| name.eq_ignore_ascii_case(value))
+                    .map(|(_, model)| *model)
+                    .ok_or_else(|| {
+                        anyhow!("unknown model {value:?}, expected one, one_lite or pro")
+                    })?,
+            ),
+            _ => bail!("unknown preference {kind:?}, expected serial, port or model"),
+        })
+    }
+}
+
+impl fmt::Display for DevicePreference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DevicePreference::Serial(serial) => write!(f, "serial:{serial}"),
+            DevicePreference::Port(port) => write!(f, "port:{port}"),
+            DevicePreference::Model(model) => {
+                let name = Self::MODELS
+                    .iter()
+                    .find(|(_, known)| known == model)
+                    .map_or("", |(name, _)| name);
+
+                write!(f, "model:{name}")
+            }
+        }
+    }
+}
+
+impl Serialize for DevicePreference {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for DevicePreference {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(|err: anyhow::Error| de::Error::custom(format!("{err:#}")))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::hotplug::VitureModel;
+
+    use super::{select, DevicePreference, UsbDevice, VitureHotPlugHandler};
+
+    fn device(address: u8, product_id: u16, serial: &str) -> UsbDevice {
+        UsbDevice {
+            product_id,
+            model: VitureHotPlugHandler::model(product_id),
+
+            bus: 1,
+            address,
+            ports: vec![address],
+
+            manufacturer: None,
+            product: None,
+            serial: Some(serial.to_string()),
+
+            open_error: None,
+        }
+    }
+
+    #[test]
+    fn describe_device() {
+        let mut device = UsbDevice {
+            product_id: 0x101d,
+            model: Some(VitureModel::Pro),
+
+            bus: 1,
+            address: 7,
+            ports: vec![3, 2],
+
+            manufacturer: Some("VITURE".to_string()),
+            product: Some("Pro XR Glasses".to_string()),
+            serial: Some("ABC123".to_string()),
+
+            open_error: None,
+        };
+
+        assert_eq!(
+            device.to_string(),
+            "Viture Pro (35ca:101d) at 1-3.2, \"VITURE Pro XR Glasses\", serial ABC123"
+        );
+
+        device.product_id = 0x1101;
+        device.model = None;
+        device.manufacturer = None;
+        device.product = None;
+        device.serial = None;
+        device.open_error = Some("Access denied (insufficient permissions)".to_string());
+
+        assert_eq!(
+            device.to_string(),
+            "unknown model (35ca:1101) at 1-3.2, usb strings unavailable: Access denied \
+             (insufficient permissions)"
+        );
+    }
+
+    #[test]
+    fn select_preferred_device() {
+        // unknown, One, Pro
+        let devices = [
+            device(2, 0x1101, "unknown"),
+            device(3, 0x1011, "one"),
+            device(4, 0x1019, "pro"),
+        ];
+
+        let selected = |preference: Option<&str>| {
+            let preference = preference.map(|p| p.parse::<DevicePreference>().unwrap());
+
+            select(&devices, preference.as_ref()).map(|device| device.address)
+        };
+
+        // unknown products are never selected
+        assert_eq!(selected(None), Some(3));
+        assert_eq!(selected(Some("model:pro")), Some(4));
+        assert_eq!(selected(Some("serial:pro")), Some(4));
+        assert_eq!(selected(Some("port:1-3")), Some(3));
+
+        // other glasses don't stand in for absent preferred ones
+        assert_eq!(selected(Some("serial:other")), None);
+        assert_eq!(selected(Some("serial:unknown")), None);
+        assert_eq!(select(&devices[..1], None), None);
+
+        assert_eq!(
+            "model:One_Lite".parse::<DevicePreference>().unwrap(),
+            DevicePreference::Model(VitureModel::OneLite)
+        );
+        assert_eq!(
+            DevicePreference::Model(VitureModel::OneLite).to_string(),
+            "model:one_lite"
+        );
+        assert!("pro".parse::<DevicePreference>().is_err());
+        assert!("model:two".parse::<DevicePreference>().is_err());
+        assert!("serial:".parse::<DevicePreference>().is_err());
+    }
+}
@@ -1,25 +1,65 @@
+mod devices;
+mod stall;
 mod viture_hotplug;
 
 use std::{
-    sync::mpsc::{channel, Receiver, Sender},
-    time::Duration,
+    sync::{
+        mpsc::{channel, Receiver},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
+pub use devices::{select, DevicePreference, UsbDevice};
+use log::{debug, info, warn};
 use rusb::{Context, HotplugBuilder, Registration, UsbContext};
+use serde::{Deserialize, Serialize};
+use stall::{Recovery, StallDetector, StreamEvent};
 use viture_hotplug::VitureHotPlugHandler;
+pub use viture_hotplug::VitureModel;
 
-use crate::{euler::EulerData, viture::viture_rs::Viture};
+use crate::{
+    config::DeviceConfig,
+    euler::ImuSample,
+    latest::LatestSender,
+    metrics::Metrics,
+    shutdown::Shutdown,
+    status::{DaemonStatus, StreamStatus},
+    viture::viture_rs::Viture,
+};
 
 #[derive(Debug, Clone, Copy)]
 enum HotPlugEvent {
-    Arrived,
-    Left,
+    Arrived(DeviceKey),
+    Left(DeviceKey),
+}
+
+/// Identifies a device for as long as it stays attached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceKey {
+    pub bus: u8,
+    pub address: u8,
+    pub product_id: u16,
+}
+
+/// Glasses currently driving the output
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DeviceInfo {
+    pub model: VitureModel,
+    pub product_id: u16,
+
+    /// Bus and address, to find the device among the attached ones
+    #[serde(default)]
+    pub bus: u8,
+    #[serde(default)]
+    pub address: u8,
 }
 
 pub struct VitureUsbController {
-    debug: bool,
-    sender: Sender<EulerData>,
+    sender: LatestSender<ImuSample>,
+    status: Arc<DaemonStatus>,
+    metrics: Arc<Metrics>,
 
     receiver: Receiver<HotPlugEvent>,
 
@@ -27,10 +67,29 @@ pub struct VitureUsbController {
     reg: Option<Registration<Context>>,
 
     viture: Option<Viture>,
+
+    /// All attached Viture devices, as of the last hotplug event
+    devices: Vec<UsbDevice>,
+
+    /// Glasses the SDK opened, set while streaming. Stays set even if the SDK failed to come
+    /// back after a stall.
+    opened: Option<UsbDevice>,
+    /// Not streaming is only warned about once, until the stream starts
+    warned: bool,
+
+    /// Set while streaming
+    stall_detector: Option<StallDetector>,
+
+    config: DeviceConfig,
 }
 
 impl VitureUsbController {
-    pub fn new(debug: bool, imu_sender: Sender<EulerData>) -> Result<Self> {
+    pub fn new(
+        imu_sender: LatestSender<ImuSample>,
+        status: Arc<DaemonStatus>,
+        metrics: Arc<Metrics>,
+        config: DeviceConfig,
+    ) -> Result<Self> {
         if !rusb::has_hotplug() {
             bail!("libusb misses hotplug capabilities! (probably update needed)");
         }
@@ -42,12 +101,13 @@ impl VitureUsbController {
             HotplugBuilder::new()
                 .enumerate(true)
                 .vendor_id(VitureHotPlugHandler::VITURE_ID_VENDOR)
-                .register(&context, Box::new(VitureHotPlugHandler::new(sender, debug)))?,
+                .register(&context, Box::new(VitureHotPlugHandler::new(sender)))?,
         );
 
         Ok(Self {
-            debug,
             sender: imu_sender,
+            status,
+            metrics,
 
             receiver,
 
@@ -55,49 +115,279 @@ impl VitureUsbController {
             reg,
 
             viture: None,
+
+            devices: Vec::new(),
+
+            opened: None,
+            warned: false,
+
+            stall_detector: None,
+
+            config,
         })
     }
 
-    pub fn check(&mut self) -> Result<()> {
-        if self.debug {
-            println!("usb controller: check")
-        }
+    /// Handles hotplug events until shutdown, then releases the glasses
+    pub fn check(&mut self, shutdown: &Shutdown) -> Result<()> {
+        debug!("check");
+
+        while !shutdown.requested() {
+            self.status.usb_heartbeat();
 
-        loop {
             self.context
                 .handle_events(Some(Duration::from_millis(20)))?;
 
+            self.check_stream();
+
             if let Ok(hotplug_event) = self.receiver.recv_timeout(Duration::from_millis(20)) {
-                if self.debug {
-                    println!("usb controller: hotplug event received: {hotplug_event:?}");
-                }
+                debug!("hotplug event received: {hotplug_event:?}");
+
+                self.refresh_devices();
 
                 match hotplug_event {
-                    HotPlugEvent::Arrived => {
-                        if self.viture.is_none() {
-                            println!("Add Viture Device");
-
-                            self.viture = Some(Viture::new({
-                                let sender = self.sender.clone();
-
-                                move |euler| {
-                                    let _ = sender.send(euler);
-                                }
-                            })?);
-                        }
+                    HotPlugEvent::Arrived(key) => self.arrived(key)?,
+                    HotPlugEvent::Left(key) => self.left(key)?,
+                }
+            }
+        }
+
+        if self.opened.is_some() {
+            self.deactivate();
+
+            info!("Released Viture Device");
+        }
+
+        Ok(())
+    }
+
+    fn arrived(&mut self, key: DeviceKey) -> Result<()> {
+        let id = device_id(key.product_id);
+
+        if VitureHotPlugHandler::model(key.product_id).is_none() {
+            warn!(
+                device_id:% = id;
+                "Unknown Viture product {id}, please report it so it can be supported \
+                 (`xr_to_opentrack_rs devices` shows the details)"
+            );
+            return Ok(());
+        }
+
+        // `init` takes no device, the sdk can't be pointed at other glasses while streaming
+        match &self.opened {
+            Some(opened) => {
+                info!(device_id:% = id; "Viture Device attached, {opened} keeps driving the output");
+                Ok(())
+            }
+            None => self.activate(),
+        }
+    }
+
+    fn left(&mut self, key: DeviceKey) -> Result<()> {
+        let id = device_id(key.product_id);
+
+        // the list may still hold the device that just left
+        self.devices.retain(|device| device.key() != key);
+
+        let streaming_from = self
+            .opened
+            .as_ref()
+            .is_some_and(|opened| opened.key() == key);
+
+        if !streaming_from {
+            debug!("inactive device {id} detached");
+
+            // may leave the selected glasses as the only ones
+            return match self.opened {
+                Some(_) => Ok(()),
+                None => self.activate(),
+            };
+        }
+
+        info!(device_id:% = id; "Remove Viture Device");
+        self.deactivate();
+
+        self.activate()
+    }
+
+    /// Starts the stream once the SDK can only open the selected glasses, that is when they
+    /// are the only supported ones attached. Otherwise waits for the others to be unplugged.
+    fn activate(&mut self) -> Result<()> {
+        let supported = self.supported();
+
+        let Some(selected) = select(&supported, self.config.prefer.as_ref()).cloned() else {
+            self.status.set_device(None);
+
+            match &self.config.prefer {
+                Some(preference) if !supported.is_empty() => self.warn_once(format!(
+                    "none of the attached glasses is the preferred {preference}, not streaming"
+                )),
+                _ => self.warned = false,
+            }
+
+            return Ok(());
+        };
+
+        if supported.len() > 1 {
+            self.warn_once(format!(
+                "{} glasses attached, the Viture SDK can't be told which to open, unplug all \
+                 but {selected} to stream from it",
+                supported.len()
+            ));
+
+            self.status.set_ambiguous_device();
+            return Ok(());
+        }
+
+        self.viture = Some(self.init_viture()?);
+        self.stall_detector = Some(StallDetector::new(
+            self.stall_timeout(),
+            self.sender.sent(),
+            Instant::now(),
+        ));
+        self.metrics.device_connects.inc();
+
+        info!(device_id:% = device_id(selected.product_id); "Add Viture Device ({selected})");
+
+        self.status.set_device(Some(DeviceInfo {
+            model: selected.model.unwrap(),
+            product_id: selected.product_id,
+            bus: selected.bus,
+            address: selected.address,
+        }));
+
+        self.opened = Some(selected);
+        self.warned = false;
+
+        Ok(())
+    }
+
+    /// Warns about not streaming only once, until the stream starts again
+    fn warn_once(&mut self, message: String) {
+        if !self.warned {
+            warn!("{message}");
+            self.warned = true;
+        }
+    }
+
+    fn deactivate(&mut self) {
+        self.opened = None;
+        self.stall_detector = None;
+        self.viture = None;
+
+        self.status.set_device(None);
+    }
+
+    fn supported(&self) -> Vec<UsbDevice> {
+        self.devices
+            .iter()
+            .filter(|device| device.model.is_some())
+            .cloned()
+            .collect()
+    }
+
+    fn stall_timeout(&self) -> Duration {
+        Duration::from_secs_f32(self.config.stall_timeout)
+    }
+
+    /// Lists the attached devices, also in the status, including unknown products. Only
+    /// devices that just arrived are opened to read their strings.
+    fn refresh_devices(&mut self) {
+        // ones that couldn't be opened get another try, permissions may have changed since
+        let mut known: Vec<UsbDevice> = self
+            .devices
+            .iter()
+            .filter(|device| device.open_error.is_none())
+            .cloned()
+            .collect();
+        known.extend(self.opened.clone());
+
+        match UsbDevice::refresh(&self.context, &known) {
+            Ok(devices) => {
+                self.status.set_devices(devices.clone());
+                self.devices = devices;
+            }
+            Err(err) => warn!("failed to list usb devices: {err:#}"),
+        }
+    }
+
+    fn init_viture(&self) -> Result<Viture> {
+        let sender = self.sender.clone();
+
+        Viture::new(move |euler| {
+            sender.send(ImuSample::new(euler));
+        })
+    }
+
+    /// Tries to get the IMU stream of connected glasses going again, if it stalled
+    fn check_stream(&mut self) {
+        let Some(stall_detector) = &mut self.stall_detector else {
+            return;
+        };
+
+        match stall_detector.check(self.sender.sent(), Instant::now()) {
+            None => (),
+            Some(StreamEvent::Recovered { attempts }) => {
+                info!("imu stream recovered after {attempts} attempts");
+
+                self.status.stream_recovered();
+            }
+            Some(StreamEvent::Failed { attempts }) => {
+                warn!(
+                    "imu stream didn't recover after {attempts} attempts, giving up until the \
+                     glasses are plugged in again"
+                );
+
+                self.status.stream_failed();
+            }
+            Some(StreamEvent::Stalled { attempt, recovery }) => {
+                warn!(
+                    "no imu samples for {:?}, attempt {attempt}: {recovery}",
+                    self.stall_timeout()
+                );
+
+                let result = match recovery {
+                    Recovery::EnableImu => match &self.viture {
+                        Some(viture) => viture.enable_imu(),
+                        None => Err(anyhow!("sdk is not initialized")),
+                    },
+                    // the sdk may pick other glasses this time
+                    Recovery::Reinit if self.supported().len() > 1 => Err(anyhow!(
+                        "other glasses are attached, the sdk might open them instead"
+                    )),
+                    Recovery::Reinit => {
+                        // drops first, the sdk only supports one instance
+                        self.viture = None;
+                        self.init_viture().map(|viture| self.viture = Some(viture))
                     }
-                    HotPlugEvent::Left => {
-                        if self.viture.is_some() {
-                            println!("Remove Viture Device");
-                            self.viture = None;
-                        }
+                };
+
+                let outcome = match result {
+                    Ok(()) => recovery.to_string(),
+                    Err(err) => {
+                        warn!("{recovery} failed: {err:#}");
+                        format!("{recovery} failed: {err:#}")
                     }
-                }
+                };
+
+                self.status.set_stream(StreamStatus {
+                    stalled: true,
+                    recovery_attempts: attempt,
+                    last_recovery: Some(outcome),
+                    failed: false,
+                });
             }
         }
     }
 }
 
+/// `vendor:product` of glasses, as logged and put in the journal
+pub fn device_id(product_id: u16) -> String {
+    format!(
+        "{:04x}:{product_id:04x}",
+        VitureHotPlugHandler::VITURE_ID_VENDOR
+    )
+}
+
 impl Drop for VitureUsbController {
     fn drop(&mut self) {
         self.context.unregister_callback(self.reg.take().unwrap());
@@ -0,0 +1,210 @@
+use std::{
+    fmt, mem,
+    time::{Duration, Instant},
+};
+
+/// Steps to get a stalled IMU stream going again, in the order they are tried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    /// Cheap, enough when the glasses only stopped streaming (e.g. after a suspend)
+    EnableImu,
+
+    /// `deinit` and `init`, for when the SDK thread died
+    Reinit,
+}
+
+impl fmt::Display for Recovery {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Recovery::EnableImu => write!(f, "re-enabling the imu"),
+            Recovery::Reinit => write!(f, "reinitializing the sdk"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamEvent {
+    /// No samples for the stall timeout (again), `recovery` should be tried now
+    Stalled { attempt: u32, recovery: Recovery },
+
+    /// Still no samples after the last attempt, nothing more is tried until samples arrive or
+    /// the glasses are plugged in again
+    Failed { attempts: u32 },
+
+    /// Samples arrive again after a stall
+    Recovered { attempts: u32 },
+}
+
+/// Notices when connected glasses stop delivering IMU samples, and escalates the recovery
+/// every time another timeout passes without samples. Reinitializations back off, each waits
+/// twice as long for an effect as the one before.
+pub struct StallDetector {
+    timeout: Duration,
+
+    samples: u64,
+    last_progress: Instant,
+
+    attempts: u32,
+    failed: bool,
+}
+
+impl StallDetector {
+    /// Attempts before the stream counts as failed, the last one gets 16 timeouts
+    pub const MAX_ATTEMPTS: u32 = 6;
+
+    pub fn new(timeout: Duration, samples: u64, now: Instant) -> Self {
+        Self {
+            timeout,
+
+            samples,
+            last_progress: now,
+
+            attempts: 0,
+            failed: false,
+        }
+    }
+
+    /// Time the last attempt gets to show an effect
+    fn wait(&self) -> Duration {
+        self.timeout * 2u32.pow(self.attempts.saturating_sub(1))
+    }
+
+    /// `samples` is the number of samples received so far
+    pub fn check(&mut self, samples: u64, now: Instant) -> Option<StreamEvent> {
+        if samples != self.samples {
+            self.samples = samples;
+            self.last_progress = now;
+
+            self.failed = false;
+            let attempts = mem::take(&mut self.attempts);
+
+            return (attempts > 0).then_some(StreamEvent::Recovered { attempts });
+        }
+
+        if self.failed || now.saturating_duration_since(self.last_progress) < self.wait() {
+            return None;
+        }
+
+        self.last_progress = now;
+
+        if self.attempts == Self::MAX_ATTEMPTS {
+            self.failed = true;
+
+            return Some(StreamEvent::Failed {
+                attempts: self.attempts,
+            });
+        }
+
+        self.attempts += 1;
+
+        Some(StreamEvent::Stalled {
+            attempt: self.attempts,
+            recovery: match self.attempts {
+                1 => Recovery::EnableImu,
+                _ => Recovery::Reinit,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{Recovery, StallDetector, StreamEvent};
+
+    #[test]
+    fn escalate_recovery() {
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+
+        let mut detector = StallDetector::new(Duration::from_secs(2), 0, start);
+
+        assert_eq!(detector.check(5, at(100)), None);
+        assert_eq!(detector.check(5, at(2000)), None);
+
+        assert_eq!(
+            detector.check(5, at(2100)),
+            Some(StreamEvent::Stalled {
+                attempt: 1,
+                recovery: Recovery::EnableImu
+            })
+        );
+        assert_eq!(detector.check(5, at(3000)), None);
+
+        // reinitializing backs off
+        for (attempt, time) in [(2, 4100), (3, 8100)] {
+            assert_eq!(detector.check(5, at(time - 100)), None);
+            assert_eq!(
+                detector.check(5, at(time)),
+                Some(StreamEvent::Stalled {
+                    attempt,
+                    recovery: Recovery::Reinit
+                })
+            );
+        }
+
+        assert_eq!(
+            detector.check(6, at(8500)),
+            Some(StreamEvent::Recovered { attempts: 3 })
+        );
+        assert_eq!(detector.check(7, at(8600)), None);
+    }
+
+    #[test]
+    fn give_up_on_repeated_stalls() {
+        let start = Instant::now();
+        let timeout = Duration::from_secs(2);
+
+        let mut detector = StallDetector::new(timeout, 0, start);
+        let mut now = start;
+        let mut stalls = Vec::new();
+
+        // a stuck device, checked every 100 ms for ten minutes
+        while now < start + Duration::from_secs(600) {
+            now += Duration::from_millis(100);
+
+            if let Some(event) = detector.check(0, now) {
+                stalls.push((event, now.duration_since(start).as_secs()));
+            }
+        }
+
+        let reinit = |attempt| StreamEvent::Stalled {
+            attempt,
+            recovery: Recovery::Reinit,
+        };
+
+        assert_eq!(
+            stalls,
+            vec![
+                (
+                    StreamEvent::Stalled {
+                        attempt: 1,
+                        recovery: Recovery::EnableImu
+                    },
+                    2
+                ),
+                (reinit(2), 4),
+                (reinit(3), 8),
+                (reinit(4), 16),
+                (reinit(5), 32),
+                (reinit(6), 64),
+                (StreamEvent::Failed { attempts: 6 }, 128),
+            ]
+        );
+
+        // samples arriving on their own still count as recovery
+        assert_eq!(
+            detector.check(1, now),
+            Some(StreamEvent::Recovered { attempts: 6 })
+        );
+        assert_eq!(detector.check(1, now + timeout / 2), None);
+        assert_eq!(
+            detector.check(1, now + timeout),
+            Some(StreamEvent::Stalled {
+                attempt: 1,
+                recovery: Recovery::EnableImu
+            })
+        );
+    }
+}
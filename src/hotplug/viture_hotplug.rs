@@ -1,76 +1,89 @@
-use std::sync::mpsc::Sender;
+use std::{fmt, sync::mpsc::Sender};
 
-use super::HotPlugEvent;
+use super::{DeviceKey, HotPlugEvent};
+use log::debug;
 use rusb::{Device, Hotplug, UsbContext};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VitureModel {
+    One,
+    OneLite,
+    Pro,
+}
+
+impl fmt::Display for VitureModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VitureModel::One => write!(f, "Viture One"),
+            VitureModel::OneLite => write!(f, "Viture One Lite"),
+            VitureModel::Pro => write!(f, "Viture Pro"),
+        }
+    }
+}
 
 pub struct VitureHotPlugHandler {
-    debug: bool,
     sender: Sender<HotPlugEvent>,
 }
 
 impl VitureHotPlugHandler {
-    pub fn new(sender: Sender<HotPlugEvent>, debug: bool) -> Self {
-        Self { debug, sender }
+    pub fn new(sender: Sender<HotPlugEvent>) -> Self {
+        Self { sender }
     }
 
-    fn check_ids<T: UsbContext>(device: &Device<T>) -> bool {
-        if let Ok(descriptor) = device.device_descriptor() {
-            if descriptor.vendor_id() == Self::VITURE_ID_VENDOR {
-                if Self::VITURE_ID_PRODUCT
-                    .iter()
-                    .any(|&product_id| product_id == descriptor.product_id())
-                {
-                    return true;
-                }
-            }
-        }
+    /// Identifies Viture devices, unknown products included so they can be reported
+    fn check_ids<T: UsbContext>(device: &Device<T>) -> Option<DeviceKey> {
+        let descriptor = device.device_descriptor().ok()?;
+
+        (descriptor.vendor_id() == Self::VITURE_ID_VENDOR).then(|| DeviceKey {
+            bus: device.bus_number(),
+            address: device.address(),
+            product_id: descriptor.product_id(),
+        })
+    }
 
-        false
+    pub fn model(product_id: u16) -> Option<VitureModel> {
+        Self::VITURE_ID_PRODUCT
+            .iter()
+            .find(|(id, _)| *id == product_id)
+            .map(|(_, model)| *model)
     }
 
     pub const VITURE_ID_VENDOR: u16 = 0x35ca;
 
-    pub const VITURE_ID_PRODUCT: [u16; 7] = [
-        0x1011, // One
-        0x1013, // One
-        0x1017, // One
-        0x1015, // One Lite
-        0x101b, // One Lite
-        0x1019, // Pro
-        0x101d, // Pro
+    pub const VITURE_ID_PRODUCT: [(u16, VitureModel); 7] = [
+        (0x1011, VitureModel::One),
+        (0x1013, VitureModel::One),
+        (0x1017, VitureModel::One),
+        (0x1015, VitureModel::OneLite),
+        (0x101b, VitureModel::OneLite),
+        (0x1019, VitureModel::Pro),
+        (0x101d, VitureModel::Pro),
     ];
 }
 
 impl<T: UsbContext> Hotplug<T> for VitureHotPlugHandler {
     fn device_arrived(&mut self, device: Device<T>) {
-        if self.debug {
-            println!(
-                "hotplug event arrived received ({:04X}:{:04X})",
-                device.device_descriptor().unwrap().vendor_id(),
-                device.device_descriptor().unwrap().product_id(),
-            );
-        }
+        debug!(
+            "hotplug event arrived received ({:04X}:{:04X})",
+            device.device_descriptor().unwrap().vendor_id(),
+            device.device_descriptor().unwrap().product_id(),
+        );
 
-        if Self::check_ids(&device) {
-            if self.debug {
-                println!("hotplug event arrived sent to channel");
-            }
+        if let Some(key) = Self::check_ids(&device) {
+            debug!("hotplug event arrived sent to channel");
 
-            let _ = self.sender.send(HotPlugEvent::Arrived);
+            let _ = self.sender.send(HotPlugEvent::Arrived(key));
         }
     }
 
     fn device_left(&mut self, device: Device<T>) {
-        if self.debug {
-            println!("hotplug event left received");
-        }
+        debug!("hotplug event left received");
 
-        if Self::check_ids(&device) {
-            if self.debug {
-                println!("hotplug event left sent to channel");
-            }
+        if let Some(key) = Self::check_ids(&device) {
+            debug!("hotplug event left sent to channel");
 
-            let _ = self.sender.send(HotPlugEvent::Left);
+            let _ = self.sender.send(HotPlugEvent::Left(key));
         }
     }
 }
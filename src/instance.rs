@@ -0,0 +1,230 @@
+use std::{
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    process, thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use log::{debug, info};
+
+const LOCK_NAME: &str = "xr_to_opentrack.pid";
+
+/// Created by systemd for the service (`RuntimeDirectory=`), only writable by its user
+const SYSTEM_DIR: &str = "/run/xr_to_opentrack";
+
+/// Held by the running daemon, so a second one fails before touching the glasses. The lock is
+/// released by the kernel when the process exits, a left over file does no harm.
+pub struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    /// Longest `--replace` waits for the running daemon, which gets 5 s to release the glasses
+    const REPLACE_TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// `$XDG_RUNTIME_DIR` for user sessions, `/run/xr_to_opentrack` for the system service.
+    /// Both are only writable by the daemon's user, nobody else can take the lock from it.
+    pub fn default_path() -> PathBuf {
+        match env::var_os("XDG_RUNTIME_DIR") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir).join(LOCK_NAME),
+            _ => Path::new(SYSTEM_DIR).join(LOCK_NAME),
+        }
+    }
+
+    /// Fails naming the PID of the running daemon, unless `replace` is set: then it is asked
+    /// to shut down with SIGTERM and waited for.
+    pub fn acquire(path: &Path, replace: bool) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| format!("failed to open lock file {}", path.display()))?;
+
+        if !try_lock(&file).with_context(|| format!("failed to lock {}", path.display()))? {
+            let pid = read_pid(&mut file);
+
+            let Some(pid) = pid.filter(|_| replace) else {
+                bail!(
+                    "another instance is already running ({}), stop it or start with --replace",
+                    pid.map_or("pid unknown".to_string(), |pid| format!("pid {pid}"))
+                );
+            };
+
+            info!("replacing the running instance (pid {pid})");
+
+            if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+                let err = io::Error::last_os_error();
+
+                // already gone is fine, the lock follows shortly
+                if err.raw_os_error() != Some(libc::ESRCH) {
+                    return Err(err).context(format!("failed to stop pid {pid}"));
+                }
+            }
+
+            let started = Instant::now();
+
+            while !try_lock(&file)? {
+                if started.elapsed() >= Self::REPLACE_TIMEOUT {
+                    bail!(
+                        "instance with pid {pid} did not exit within {:?}",
+                        Self::REPLACE_TIMEOUT
+                    );
+                }
+
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(file, "{}", process::id())
+            .with_context(|| format!("failed to write {}", path.display()))?;
+
+        debug!("holding instance lock {}", path.display());
+
+        Ok(Self { _file: file })
+    }
+}
+
+/// `false` if another process holds the lock
+fn try_lock(file: &File) -> io::Result<bool> {
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+
+    let err = io::Error::last_os_error();
+
+    match err.raw_os_error() {
+        Some(libc::EWOULDBLOCK) => Ok(false),
+        _ => Err(err),
+    }
+}
+
+/// `None` while the holder did not get to write it yet. Never 0 or negative, `kill` would
+/// signal whole process groups then.
+fn read_pid(file: &mut File) -> Option<libc::pid_t> {
+    let mut content = String::new();
+
+    file.rewind().ok()?;
+    file.read_to_string(&mut content).ok()?;
+
+    content.trim().parse().ok().filter(|pid| *pid > 0)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        env, fs,
+        process::{self, Command, Stdio},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::{read_pid, InstanceLock};
+
+    #[test]
+    fn single_instance() {
+        let path = env::temp_dir().join(format!("xr_to_opentrack_test_{}.pid", process::id()));
+
+        let lock = InstanceLock::acquire(&path, false).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\n", process::id())
+        );
+
+        // a lock on another open file conflicts, also within one process
+        let err = InstanceLock::acquire(&path, false).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "another instance is already running (pid {}), stop it or start with --replace",
+                process::id()
+            )
+        );
+
+        drop(lock);
+        InstanceLock::acquire(&path, false).unwrap();
+
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn invalid_pid() {
+        let path = env::temp_dir().join(format!("xr_to_opentrack_pid_{}", process::id()));
+
+        for (content, pid) in [
+            ("42\n", Some(42)),
+            ("0\n", None),
+            ("-1\n", None),
+            ("", None),
+        ] {
+            fs::write(&path, content).unwrap();
+
+            assert_eq!(read_pid(&mut fs::File::open(&path).unwrap()), pid);
+        }
+
+        let _ = fs::remove_file(&path);
+    }
+
+    /// Set for the child process of `replace_running_instance`, holding the lock there
+    const HOLDER_ENV: &str = "XR_TO_OPENTRACK_LOCK_HOLDER";
+
+    #[test]
+    #[ignore = "started by replace_running_instance"]
+    fn lock_holder() {
+        if let Some(path) = env::var_os(HOLDER_ENV) {
+            let _lock = InstanceLock::acquire(path.as_ref(), false).unwrap();
+
+            // ended by the SIGTERM of --replace
+            thread::sleep(Duration::from_secs(60));
+        }
+    }
+
+    #[test]
+    fn replace_running_instance() {
+        let path = env::temp_dir().join(format!("xr_to_opentrack_replace_{}.pid", process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut holder = Command::new(env::current_exe().unwrap())
+            .args(["--exact", "instance::test::lock_holder", "--ignored"])
+            .env(HOLDER_ENV, &path)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let holder_pid = holder.id().to_string();
+
+        let started = Instant::now();
+
+        while fs::read_to_string(&path).map_or(true, |pid| pid.trim() != holder_pid) {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "holder not started"
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert!(InstanceLock::acquire(&path, false).is_err());
+
+        let _lock = InstanceLock::acquire(&path, true).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\n", process::id())
+        );
+
+        // terminated by the signal instead of running into the sleep
+        assert!(!holder.wait().unwrap().success());
+
+        let _ = fs::remove_file(&path);
+    }
+}
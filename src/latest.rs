@@ -0,0 +1,161 @@
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering::SeqCst},
+    mpsc::RecvTimeoutError,
+    Arc, Condvar, Mutex,
+};
+use std::time::{Duration, Instant};
+
+struct Shared<T> {
+    slot: Mutex<Option<T>>,
+    ready: Condvar,
+
+    senders: AtomicUsize,
+
+    sent: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Creates a single-slot handoff that only keeps the newest value. Sending never blocks,
+/// a value that wasn't picked up in time gets replaced and counted as dropped.
+pub fn latest<T>() -> (LatestSender<T>, LatestReceiver<T>) {
+    let shared = Arc::new(Shared {
+        slot: Mutex::new(None),
+        ready: Condvar::new(),
+
+        senders: AtomicUsize::new(1),
+
+        sent: AtomicU64::new(0),
+        dropped: AtomicU64::new(0),
+    });
+
+    (
+        LatestSender {
+            shared: shared.clone(),
+        },
+        LatestReceiver { shared },
+    )
+}
+
+pub struct LatestSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> LatestSender<T> {
+    pub fn send(&self, value: T) {
+        let replaced = self.shared.slot.lock().unwrap().replace(value);
+
+        self.shared.sent.fetch_add(1, SeqCst);
+
+        if replaced.is_some() {
+            self.shared.dropped.fetch_add(1, SeqCst);
+        }
+
+        self.shared.ready.notify_one();
+    }
+
+    /// Number of values sent so far, by any sender
+    pub fn sent(&self) -> u64 {
+        self.shared.sent.load(SeqCst)
+    }
+}
+
+impl<T> Clone for LatestSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, SeqCst);
+
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for LatestSender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, SeqCst) == 1 {
+            // take the lock, so a receiver can't miss the wakeup between its check and wait
+            let _slot = self.shared.slot.lock().unwrap();
+            self.shared.ready.notify_all();
+        }
+    }
+}
+
+pub struct LatestReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> LatestReceiver<T> {
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut slot = self.shared.slot.lock().unwrap();
+
+        loop {
+            if let Some(value) = slot.take() {
+                return Ok(value);
+            }
+
+            if self.shared.senders.load(SeqCst) == 0 {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+
+            if remaining.is_zero() {
+                return Err(RecvTimeoutError::Timeout);
+            }
+
+            slot = self.shared.ready.wait_timeout(slot, remaining).unwrap().0;
+        }
+    }
+
+    /// Number of values handed to the sender so far
+    pub fn sent(&self) -> u64 {
+        self.shared.sent.load(SeqCst)
+    }
+
+    /// Number of values that were replaced before the receiver picked them up
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::mpsc::RecvTimeoutError, thread, time::Duration};
+
+    use super::latest;
+
+    #[test]
+    fn keeps_newest() {
+        let (sender, receiver) = latest();
+
+        sender.send(1);
+        sender.send(2);
+        sender.send(3);
+
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(1)), Ok(3));
+        assert_eq!(receiver.sent(), 3);
+        assert_eq!(receiver.dropped(), 2);
+
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_millis(1)),
+            Err(RecvTimeoutError::Timeout)
+        );
+    }
+
+    #[test]
+    fn disconnect() {
+        let (sender, receiver) = latest::<u32>();
+        let second_sender = sender.clone();
+
+        drop(sender);
+        second_sender.send(5);
+
+        thread::spawn(move || drop(second_sender));
+
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(5));
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(5)),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+}
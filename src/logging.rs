@@ -0,0 +1,362 @@
+use std::{
+    env, fmt,
+    io::{self, Write},
+    mem,
+    os::unix::net::UnixDatagram,
+    str::FromStr,
+    sync::{OnceLock, RwLock},
+};
+
+use anyhow::{bail, Context, Result};
+use log::{kv, Level, LevelFilter, Log, Metadata, Record};
+
+/// Socket of journald's native protocol
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+const SYSLOG_IDENTIFIER: &str = "xr_to_opentrack";
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Log level per module, written like `info,relay=debug,control=trace`. Modules are named
+/// without the crate (`main` for the crate root), the most specific directive wins.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFilter {
+    default: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl LogFilter {
+    pub fn level(&self, target: &str) -> LevelFilter {
+        let module = module_name(target);
+
+        self.modules
+            .iter()
+            .filter(|(name, _)| {
+                module
+                    .strip_prefix(name.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(name, _)| name.len())
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    /// Most verbose level of any module, records above it are skipped without asking the logger
+    fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, Ord::max)
+    }
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        Self {
+            default: LevelFilter::Info,
+            modules: Vec::new(),
+        }
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut filter = Self::default();
+
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((module, level)) => {
+                    let module = module.trim();
+
+                    if module.is_empty()
+                        || !module
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+                    {
+                        bail!("invalid module `{module}` in log filter");
+                    }
+
+                    filter
+                        .modules
+                        .retain(|(existing, _)| existing.as_str() != module);
+                    filter
+                        .modules
+                        .push((module.to_string(), parse_level(level)?));
+                }
+                None => filter.default = parse_level(directive)?,
+            }
+        }
+
+        Ok(filter)
+    }
+}
+
+impl fmt::Display for LogFilter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.default.as_str().to_ascii_lowercase())?;
+
+        for (module, level) in &self.modules {
+            write!(f, ",{module}={}", level.as_str().to_ascii_lowercase())?;
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter> {
+    level.trim().parse().map_err(|_| {
+        anyhow::anyhow!(
+            "invalid log level `{}`, expected off, error, warn, info, debug or trace",
+            level.trim()
+        )
+    })
+}
+
+/// Module path of a record, relative to this crate
+fn module_name(target: &str) -> &str {
+    let crate_name = env!("CARGO_CRATE_NAME");
+
+    match target.strip_prefix(crate_name) {
+        Some("") => "main",
+        Some(rest) => rest.strip_prefix("::").unwrap_or(target),
+        None => target,
+    }
+}
+
+/// Writes to stderr, or straight to journald when stderr is connected to the journal. Key
+/// values of a record (`frame_number = 3; "..."`) become journal fields.
+struct Logger {
+    filter: RwLock<LogFilter>,
+    journal: Option<UnixDatagram>,
+}
+
+/// Installs the logger, has to be called once before anything logs
+pub fn init(filter: LogFilter) -> Result<()> {
+    let journal = if connected_to_journal() {
+        Some(UnixDatagram::unbound().context("failed to create journal socket")?)
+    } else {
+        None
+    };
+
+    log::set_max_level(filter.max_level());
+
+    let logger = LOGGER.get_or_init(|| Logger {
+        filter: RwLock::new(filter),
+        journal,
+    });
+
+    if log::set_logger(logger).is_err() {
+        bail!("logger is already installed");
+    }
+
+    Ok(())
+}
+
+/// Filter in effect, `None` before [`init`]
+pub fn filter() -> Option<LogFilter> {
+    Some(LOGGER.get()?.filter.read().unwrap().clone())
+}
+
+/// Replaces the filter of the running logger
+pub fn set_filter(filter: LogFilter) {
+    if let Some(logger) = LOGGER.get() {
+        log::set_max_level(filter.max_level());
+        *logger.filter.write().unwrap() = filter;
+    }
+}
+
+/// systemd announces the journal as `device:inode` of the stream it connects stderr to
+fn connected_to_journal() -> bool {
+    let Ok(stream) = env::var("JOURNAL_STREAM") else {
+        return false;
+    };
+
+    let mut stat: libc::stat = unsafe { mem::zeroed() };
+
+    if unsafe { libc::fstat(libc::STDERR_FILENO, &mut stat) } != 0 {
+        return false;
+    }
+
+    stream == format!("{}:{}", stat.st_dev, stat.st_ino)
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.read().unwrap().level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let mut fields = Fields(Vec::new());
+        let _ = record.key_values().visit(&mut fields);
+
+        if let Some(journal) = &self.journal {
+            // too large entries would need a memfd, those few go to stderr instead
+            if journal
+                .send_to(&journal_entry(record, &fields.0), JOURNAL_SOCKET)
+                .is_ok()
+            {
+                return;
+            }
+        }
+
+        let mut line = format!(
+            "{:<5} {}: {}",
+            record.level(),
+            module_name(record.target()),
+            record.args()
+        );
+
+        for (key, value) in &fields.0 {
+            line.push_str(&format!(" {key}={value}"));
+        }
+
+        let _ = writeln!(io::stderr().lock(), "{line}");
+    }
+
+    fn flush(&self) {}
+}
+
+struct Fields(Vec<(String, String)>);
+
+impl<'kvs> kv::VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push((key.to_string(), value.to_string()));
+
+        Ok(())
+    }
+}
+
+/// Entry in journald's native protocol, see systemd's `JOURNAL_NATIVE_PROTOCOL`
+fn journal_entry(record: &Record, fields: &[(String, String)]) -> Vec<u8> {
+    let priority = match record.level() {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    };
+
+    let mut entry = Vec::new();
+
+    push_field(&mut entry, "PRIORITY", &priority.to_string());
+    push_field(&mut entry, "SYSLOG_IDENTIFIER", SYSLOG_IDENTIFIER);
+    push_field(&mut entry, "MESSAGE", &record.args().to_string());
+    push_field(&mut entry, "TARGET", module_name(record.target()));
+
+    if let Some(file) = record.file() {
+        push_field(&mut entry, "CODE_FILE", file);
+    }
+
+    if let Some(line) = record.line() {
+        push_field(&mut entry, "CODE_LINE", &line.to_string());
+    }
+
+    for (key, value) in fields {
+        let name = journal_field_name(key);
+
+        if !name.is_empty() {
+            push_field(&mut entry, &name, value);
+        }
+    }
+
+    entry
+}
+
+/// Journal fields are upper case letters, digits and underscores
+fn journal_field_name(key: &str) -> String {
+    let name: String = key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    // leading underscores are reserved for the fields journald adds itself
+    name.trim_start_matches(|c: char| c == '_' || c.is_ascii_digit())
+        .to_string()
+}
+
+fn push_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+
+    // values with newlines are sent length prefixed
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+#[cfg(test)]
+mod test {
+    use log::{Level, LevelFilter, Record};
+
+    use super::{journal_entry, LogFilter};
+
+    #[test]
+    fn filter_per_module() {
+        let filter: LogFilter = "warn, relay=debug,control::auth=trace,relay=info"
+            .parse()
+            .unwrap();
+
+        assert_eq!(filter.to_string(), "warn,control::auth=trace,relay=info");
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+
+        assert_eq!(filter.level("xr_to_opentrack_rs"), LevelFilter::Warn);
+        assert_eq!(filter.level("xr_to_opentrack_rs::relay"), LevelFilter::Info);
+        assert_eq!(
+            filter.level("xr_to_opentrack_rs::control::auth"),
+            LevelFilter::Trace
+        );
+        assert_eq!(
+            filter.level("xr_to_opentrack_rs::control"),
+            LevelFilter::Warn
+        );
+        assert_eq!(
+            filter.level("xr_to_opentrack_rs::relayed"),
+            LevelFilter::Warn
+        );
+
+        assert_eq!(
+            "main=debug"
+                .parse::<LogFilter>()
+                .unwrap()
+                .level("xr_to_opentrack_rs"),
+            LevelFilter::Debug
+        );
+
+        assert!("loud".parse::<LogFilter>().is_err());
+        assert!("relay=".parse::<LogFilter>().is_err());
+        assert!("=debug".parse::<LogFilter>().is_err());
+    }
+
+    #[test]
+    fn journal_fields() {
+        let entry = journal_entry(
+            &Record::builder()
+                .level(Level::Debug)
+                .target("xr_to_opentrack_rs::output")
+                .args(format_args!("sent\nframe"))
+                .build(),
+            &[("frame_number".to_string(), "42".to_string())],
+        );
+
+        let mut expected = b"PRIORITY=7\nSYSLOG_IDENTIFIER=xr_to_opentrack\nMESSAGE\n".to_vec();
+        expected.extend_from_slice(&10u64.to_le_bytes());
+        expected.extend_from_slice(b"sent\nframe\nTARGET=output\nFRAME_NUMBER=42\n");
+
+        assert_eq!(entry, expected);
+    }
+}
@@ -1,78 +1,427 @@
+mod auto_profile;
+mod config;
+mod control;
+mod doctor;
 mod euler;
 mod ftok_ipc;
 mod hotplug;
+mod instance;
+mod latest;
+mod logging;
+mod metrics;
+mod notify;
 mod open_track_data;
+mod open_track_target;
+mod output;
+mod profile;
+mod relay;
+mod reload;
+mod replay;
+mod scheduler;
+mod shutdown;
+mod status;
+mod supervisor;
 mod viture;
 
-use crate::euler::EulerData;
-use anyhow::Result;
-use clap::Parser;
+use crate::euler::{EulerState, ImuSample};
+use anyhow::{anyhow, bail, Context, Result};
+use auto_profile::ProcessWatcher;
+use clap::{ArgAction, Parser, Subcommand};
+use config::{Config, ConfigWriter};
+use control::{
+    protocol::{RequestBody, ResponseBody},
+    ControlServer, Controller,
+};
 use euler::EulerHandler;
-use hotplug::VitureUsbController;
-use open_track_data::OpenTrackData;
+use hotplug::{select, UsbDevice, VitureUsbController};
+use instance::InstanceLock;
+use latest::{latest, LatestReceiver};
+use log::{debug, error, info, warn};
+use metrics::Metrics;
+use notify::Notifier;
+use open_track_data::PoseField;
+use open_track_target::{Destination, OpenTrackTarget};
+use output::Output;
+use profile::ProfileList;
+use relay::RelayInput;
+use reload::ConfigWatcher;
 use ring_channel::ring_channel;
+use scheduler::{OutputMode, OutputScheduler};
 use serde::{Deserialize, Serialize};
-use serde_json::{from_str, to_string};
+use serde_json::{from_value, to_string, to_string_pretty, Value};
+use shutdown::Shutdown;
+use status::{DaemonStatus, Status};
 use std::{
-    io::{Read, Write},
-    net::{Ipv4Addr, TcpListener, TcpStream, UdpSocket},
+    fs::{self, File},
+    io::{self, Write},
+    net::SocketAddr,
     num::NonZeroUsize,
-    sync::{
-        mpsc::{channel, Receiver},
-        Arc, Mutex,
-    },
-    thread,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{mpsc::RecvTimeoutError, Arc},
+    time::{Duration, Instant},
 };
+use supervisor::Supervisor;
+
+/// Longest the output loop waits for an IMU sample before checking on everything else
+const IDLE_TIMEOUT: Duration = Duration::from_secs(1);
 
 /// Tool to provide viture imu data to OpenTrack
-#[derive(Debug, Parser)]
+#[derive(Debug, Clone, Parser)]
 #[command(version = "0.1")]
 #[command(about, long_about = None)]
 struct Args {
-    /// IP on which OpenTrack listens
-    #[arg(short = 'i', long)]
-    #[arg(default_value = "127.0.0.1")]
-    open_track_ip: Ipv4Addr,
-
-    /// Port on which OpenTrack listens
-    #[arg(short = 'p', long, default_value_t = 4242)]
-    open_track_port: u16,
-
-    /// Enable debug logging
-    #[arg(short, long)]
+    #[command(subcommand)]
+    command: CliCommand,
+
+    /// Config file to use instead of /etc/xr_to_opentrack/config.toml and
+    /// $XDG_CONFIG_HOME/xr_to_opentrack/config.toml
+    #[arg(short, long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Path of the control socket [default: $XDG_RUNTIME_DIR/xr_to_opentrack.sock, or
+    /// /run/xr_to_opentrack.sock without a user session. Clients also fall back to the latter
+    /// when no daemon runs in the session.]
+    #[arg(long, global = true)]
+    control_socket: Option<PathBuf>,
+
+    /// Additionally accept control connections over TCP on this address (e.g. 127.0.0.1:4244).
+    /// Clients only connect over TCP when given this option, `control.tcp` of the config
+    /// does not change where they connect.
+    #[arg(long, global = true)]
+    control_tcp: Option<SocketAddr>,
+
+    /// File containing the token TCP control clients have to present. Required when
+    /// --control-tcp binds to anything but loopback.
+    #[arg(long, global = true)]
+    control_token_file: Option<PathBuf>,
+
+    /// Log level, optionally per module (e.g. `info,relay=debug`) [default: info]
+    #[arg(long, global = true)]
+    log_level: Option<String>,
+
+    /// Shorthand for `--log-level debug`
+    #[arg(short, long, global = true)]
     debug: bool,
 
-    /// Enable verbose logging
-    #[arg(short, long)]
+    /// Shorthand for `--log-level trace`, which logs every pose
+    #[arg(short, long, global = true)]
     verbose: bool,
+}
+
+/// Options of the daemon, on top of its config files
+#[derive(Debug, Clone, clap::Args)]
+struct RunArgs {
+    /// Address on which OpenTrack listens, as `host:port` (hostname, IPv4 or IPv6)
+    /// [default: 127.0.0.1:4242]
+    #[arg(short = 't', long = "target")]
+    open_track_target: Option<Destination>,
 
-    /// Recenters to current position
+    /// Send to OpenTrack at a fixed rate (1 to 1000 Hz) instead of once per IMU sample
+    #[arg(short = 'r', long)]
+    rate: Option<f32>,
+
+    /// How poses are produced between IMU samples when a fixed rate is set [default: interpolate]
+    #[arg(long, value_enum)]
+    output_mode: Option<OutputMode>,
+
+    /// Listen for OpenTrack UDP packets of another tracker on this address (e.g. 0.0.0.0:4243)
+    #[arg(long)]
+    relay: Option<SocketAddr>,
+
+    /// Fields taken from the relay input, all others come from the glasses [default: x,y,z]
+    #[arg(long, value_enum, value_delimiter = ',')]
+    relay_fields: Option<Vec<PoseField>>,
+
+    /// Serve Prometheus metrics over HTTP on this address (e.g. 127.0.0.1:9464)
     #[arg(long)]
-    center: bool,
+    metrics: Option<SocketAddr>,
+
+    /// Profile to start with [default: default]
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Save changes made at runtime to the config file right away
+    #[arg(long)]
+    autosave: bool,
+
+    /// Stop an already running instance and take over, instead of failing
+    #[arg(long)]
+    replace: bool,
+}
+
+impl Args {
+    /// Config from the config files, with the options given on the command line taking precedence
+    fn config(&self) -> Result<Config> {
+        let mut config = Config::load(self.config.as_deref())?;
+
+        if let Some(path) = &self.control_socket {
+            config.control.socket = Some(path.clone());
+        }
+
+        if let Some(address) = self.control_tcp {
+            match self.command {
+                CliCommand::Run(_) => config.control.tcp = Some(address),
+                _ => config.control.client_tcp = Some(address),
+            }
+        }
+
+        if let Some(path) = &self.control_token_file {
+            config.control.token_file = Some(path.clone());
+        }
+
+        if let Some(log_level) = &self.log_level {
+            config.log_level = log_level.clone();
+        } else if self.verbose {
+            config.log_level = "trace".to_string();
+        } else if self.debug {
+            config.log_level = "debug".to_string();
+        }
+
+        if let CliCommand::Run(run) = &self.command {
+            run.apply(&mut config)?;
+        }
+
+        config.log_filter()?;
+
+        Ok(config)
+    }
+}
+
+impl RunArgs {
+    fn apply(&self, config: &mut Config) -> Result<()> {
+        if let Some(target) = &self.open_track_target {
+            config.target = target.clone();
+        }
+
+        if let Some(rate) = self.rate {
+            config.rate = Some(rate);
+        }
+
+        if let Some(output_mode) = self.output_mode {
+            config.output_mode = output_mode;
+        }
+
+        if let Some(address) = self.relay {
+            config.relay.address = Some(address);
+        }
+
+        if let Some(fields) = &self.relay_fields {
+            config.relay.fields = fields.clone();
+        }
+
+        if let Some(address) = self.metrics {
+            config.metrics = Some(address);
+        }
+
+        if let Some(profile) = &self.profile {
+            config.profile = profile.clone();
+        }
+
+        if self.autosave {
+            config.autosave = true;
+        }
+
+        config.validate()
+    }
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum CliCommand {
+    /// Run the daemon, sending the orientation of the glasses to OpenTrack
+    Run(RunArgs),
+
+    /// Change the running daemon
+    Ctl {
+        #[command(subcommand)]
+        command: CtlCommand,
+    },
 
-    /// Scale yaw output
-    #[arg(long = "sy")]
-    scale_yaw: Option<f32>,
+    /// Show the state of the running daemon
+    Status {
+        /// Print machine readable JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// List attached Viture glasses with model, USB port and USB strings
+    Devices {
+        /// Print machine readable JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Record raw and processed poses of the running daemon as JSON lines
+    #[command(alias = "subscribe")]
+    Record {
+        /// Maximum number of poses per second
+        #[arg(short, long, default_value_t = 30.0)]
+        rate: f32,
+
+        /// File to write to instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
 
-    /// Scale pitch output
-    #[arg(long = "sp")]
-    scale_pitch: Option<f32>,
+    /// Send the processed poses of a recording to OpenTrack, with the recorded timing
+    Replay {
+        /// Recording made with `record`, `-` for stdin
+        file: PathBuf,
 
-    /// Scale roll output
-    #[arg(long = "sr")]
-    scale_roll: Option<f32>,
+        /// Address on which OpenTrack listens [default: the `target` of the config]
+        #[arg(short = 't', long = "target")]
+        open_track_target: Option<Destination>,
+
+        /// Playback speed, 2 plays twice as fast
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+    },
+
+    /// Check the setup: config, USB access, glasses, daemon and OpenTrack target
+    Doctor,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum CtlCommand {
+    /// Make the current orientation the center
+    Recenter,
+
+    /// Scale yaw output, negative values invert it
+    ScaleYaw {
+        /// Factor applied to the yaw angle, 1 keeps it as is
+        #[arg(allow_negative_numbers = true)]
+        scale: f32,
+    },
+
+    /// Scale pitch output, negative values invert it
+    ScalePitch {
+        /// Factor applied to the pitch angle, 1 keeps it as is
+        #[arg(allow_negative_numbers = true)]
+        scale: f32,
+    },
+
+    /// Scale roll output, negative values invert it
+    ScaleRoll {
+        /// Factor applied to the roll angle, 1 keeps it as is
+        #[arg(allow_negative_numbers = true)]
+        scale: f32,
+    },
 
     /// Invert yaw output
-    #[arg(long = "iy")]
-    invert_yaw: Option<bool>,
+    InvertYaw {
+        /// `true` inverts, `false` restores the original direction
+        #[arg(action = ArgAction::Set)]
+        invert: bool,
+    },
 
     /// Invert pitch output
-    #[arg(long = "ip")]
-    invert_pitch: Option<bool>,
+    InvertPitch {
+        /// `true` inverts, `false` restores the original direction
+        #[arg(action = ArgAction::Set)]
+        invert: bool,
+    },
 
     /// Invert roll output
-    #[arg(long = "ir")]
-    invert_roll: Option<bool>,
+    InvertRoll {
+        /// `true` inverts, `false` restores the original direction
+        #[arg(action = ArgAction::Set)]
+        invert: bool,
+    },
+
+    /// Response curve exponent, above 1 damps small movements
+    Curve {
+        /// 1 is linear, e.g. 1.5 for finer control around the center
+        exponent: f32,
+    },
+
+    /// Smoothing factor in [0, 1), 0 disables smoothing
+    Smoothing {
+        /// Weight of the previous pose, higher is smoother but lags more
+        factor: f32,
+    },
+
+    /// Write the center and profiles to the daemon's config file
+    SaveConfig,
+
+    /// List and manage the profiles
+    Profile {
+        #[command(subcommand)]
+        command: Option<ProfileCommand>,
+    },
+
+    /// Show the log filter, or replace it (e.g. `info,relay=debug`)
+    LogLevel {
+        /// New filter, the current one is shown without
+        filter: Option<String>,
+    },
+}
+
+impl CtlCommand {
+    fn request(self) -> RequestBody {
+        let command = match self {
+            CtlCommand::Recenter => Command::Recenter,
+            CtlCommand::ScaleYaw { scale } => Command::ScaleYaw(scale),
+            CtlCommand::ScalePitch { scale } => Command::ScalePitch(scale),
+            CtlCommand::ScaleRoll { scale } => Command::ScaleRoll(scale),
+            CtlCommand::InvertYaw { invert } => Command::InvertYaw(invert),
+            CtlCommand::InvertPitch { invert } => Command::InvertPitch(invert),
+            CtlCommand::InvertRoll { invert } => Command::InvertRoll(invert),
+            CtlCommand::Curve { exponent } => Command::Curve(exponent),
+            CtlCommand::Smoothing { factor } => Command::Smoothing(factor),
+            CtlCommand::SaveConfig => return RequestBody::SaveConfig,
+            CtlCommand::LogLevel { filter } => return RequestBody::LogLevel { filter },
+            CtlCommand::Profile { command } => match command {
+                None | Some(ProfileCommand::List) => return RequestBody::Profiles,
+                Some(ProfileCommand::Activate { name }) => {
+                    return RequestBody::ActivateProfile { name }
+                }
+                Some(ProfileCommand::Create { name }) => {
+                    return RequestBody::CreateProfile { name }
+                }
+                Some(ProfileCommand::Clone { source, name }) => {
+                    return RequestBody::CloneProfile { source, name }
+                }
+                Some(ProfileCommand::Delete { name }) => {
+                    return RequestBody::DeleteProfile { name }
+                }
+            },
+        };
+
+        RequestBody::Apply(vec![command])
+    }
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum ProfileCommand {
+    /// List all profiles, marking the active one
+    List,
+
+    /// Switch to a profile
+    Activate {
+        /// Profile to activate
+        name: String,
+    },
+
+    /// Create a profile with default settings
+    Create {
+        /// Letters, digits, `-` and `_`
+        name: String,
+    },
+
+    /// Create a profile as a copy of another
+    Clone {
+        /// Profile to copy
+        source: String,
+
+        /// Name of the copy: letters, digits, `-` and `_`
+        name: String,
+    },
+
+    /// Delete a profile
+    Delete {
+        /// Profile to delete, it must not be active
+        name: String,
+    },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -86,199 +435,510 @@ enum Command {
     InvertYaw(bool),
     InvertPitch(bool),
     InvertRoll(bool),
-}
 
-const TCP_SOCKET: u16 = 4244;
+    Curve(f32),
+    Smoothing(f32),
+}
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     let args = Args::parse();
-
-    if let Some(commands) = check_cli_commands(&args) {
-        let mut client = TcpStream::connect(("127.0.0.1", TCP_SOCKET))?;
-
-        for command in commands {
-            client.write_all(to_string(&command)?.as_bytes())?;
-            client.write(";".as_bytes())?;
+    let config = match args.config() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("error: {err:#}");
+            return Ok(ExitCode::FAILURE);
         }
+    };
 
-        return Ok(());
+    if let Err(err) = logging::init(config.log_filter()?) {
+        eprintln!("error: {err:#}");
+        return Ok(ExitCode::FAILURE);
     }
 
-    if args.debug {
-        println!("Starting program ...");
+    match args.command.clone() {
+        CliCommand::Run(run) => run_daemon(&args, &config, &run),
+        CliCommand::Ctl {
+            command:
+                CtlCommand::Profile {
+                    command: None | Some(ProfileCommand::List),
+                },
+        } => Ok(print_profiles(&config)),
+        CliCommand::Ctl { command } => Ok(send_request(&config, command.request())),
+        CliCommand::Status { json } => Ok(print_status(&config, json)),
+        CliCommand::Devices { json } => Ok(print_devices(&config, json)),
+        CliCommand::Record { rate, output } => Ok(record_poses(&config, rate, output.as_deref())),
+        CliCommand::Replay {
+            file,
+            open_track_target,
+            speed,
+        } => Ok(replay::replay(
+            &file,
+            open_track_target.unwrap_or(config.target),
+            speed,
+        )),
+        CliCommand::Doctor => Ok(doctor::run(&config, &Config::paths(args.config.as_deref()))),
     }
+}
 
-    let socket = UdpSocket::bind("127.0.0.1:0")?;
-    socket.connect((args.open_track_ip, args.open_track_port))?;
+fn run_daemon(args: &Args, config: &Config, run: &RunArgs) -> Result<ExitCode> {
+    debug!("Starting program ...");
 
-    if args.debug {
-        println!("Created udp socket");
+    // before anything is bound or the glasses are touched, released on exit
+    let _instance_lock = match InstanceLock::acquire(&InstanceLock::default_path(), run.replace) {
+        Ok(lock) => lock,
+        Err(err) => {
+            error!("{err:#}");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
 
-        println!(
-            "Connected udp socket to {:?}:{}",
-            args.open_track_ip, args.open_track_port
-        );
+    let shutdown = Shutdown::install()?;
+
+    let status = Arc::new(DaemonStatus::new(config.target.clone(), config.rate));
+
+    let supervisor = Supervisor::new();
+    let metrics = Arc::new(Metrics::default());
+
+    if let Some(address) = config.metrics {
+        metrics.serve(address, &supervisor)?;
     }
 
-    let (sender, receiver) = channel();
-    let mut viture_usb_controller = VitureUsbController::new(args.debug, sender)?;
+    let (sender, receiver) = latest();
+
+    // fails early if libusb is unusable, restarts build a new controller
+    let mut viture_usb_controller = Some(VitureUsbController::new(
+        sender.clone(),
+        status.clone(),
+        metrics.clone(),
+        config.device.clone(),
+    )?);
+
+    debug!("created everything: start loops");
+
+    let usb_thread = supervisor.spawn("usb controller", {
+        let status = status.clone();
+        let metrics = metrics.clone();
+        let shutdown = shutdown.clone();
+        let device_config = config.device.clone();
+
+        move || {
+            let mut controller = match viture_usb_controller.take() {
+                Some(controller) => controller,
+                None => VitureUsbController::new(
+                    sender.clone(),
+                    status.clone(),
+                    metrics.clone(),
+                    device_config.clone(),
+                )?,
+            };
+
+            controller.check(&shutdown)
+        }
+    });
 
-    if args.debug {
-        println!("created everything: start loops");
+    let result = send_to_opentrack(
+        args,
+        config,
+        receiver,
+        status,
+        metrics,
+        &shutdown,
+        &supervisor,
+    );
+
+    // the glasses are released when the usb controller drops, also when exiting on an error
+    shutdown.request();
+    let _ = usb_thread.join();
+
+    if let Err(err) = result {
+        error!("{err:#}");
+        return Ok(ExitCode::FAILURE);
     }
 
-    thread::spawn(move || viture_usb_controller.check());
-    send_to_opentrack(socket, receiver, args.debug, args.verbose)?;
+    Ok(ExitCode::SUCCESS)
+}
 
-    Ok(())
+fn print_status(config: &Config, json: bool) -> ExitCode {
+    request(config, RequestBody::Status, |data| {
+        let Some(data) = data else {
+            bail!("daemon answered without status");
+        };
+
+        if json {
+            println!("{}", to_string_pretty(&data)?);
+        } else {
+            let status: Status = from_value(data).context("unexpected status answer")?;
+            println!("{status}");
+        }
+
+        Ok(())
+    })
 }
 
-fn send_to_opentrack(
-    socket: UdpSocket,
-    receiver: Receiver<EulerData>,
-    debug: bool,
-    verbose: bool,
-) -> Result<()> {
-    if debug {
-        println!("send to opentrack: start");
+fn print_devices(config: &Config, json: bool) -> ExitCode {
+    let devices = match rusb::Context::new()
+        .map_err(anyhow::Error::from)
+        .and_then(|context| UsbDevice::list(&context))
+    {
+        Ok(devices) => devices,
+        Err(err) => {
+            eprintln!("failed to list usb devices: {err:#}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if json {
+        println!("{}", to_string_pretty(&devices).unwrap());
+        return ExitCode::SUCCESS;
     }
 
-    let mut framenumber = 0;
-    let euler_handler: Arc<Mutex<EulerHandler>> = Arc::new(Mutex::new(EulerHandler::new(debug)));
+    if devices.is_empty() {
+        println!("no Viture glasses attached");
+    }
 
-    let mut server = TcpListener::bind(("127.0.0.1", TCP_SOCKET))?;
-    let (euler_sender, euler_receiver) = ring_channel(NonZeroUsize::new(1).unwrap());
+    // marks the glasses the daemon streams from, once they are the only supported ones
+    let selected = select(&devices, config.device.prefer.as_ref()).map(UsbDevice::key);
 
-    thread::spawn({
-        let euler_handler = euler_handler.clone();
+    for device in &devices {
+        let marker = if Some(device.key()) == selected {
+            '*'
+        } else {
+            ' '
+        };
+        println!("{marker} {device}");
+    }
 
-        move || loop {
-            match check_tcp_command(&mut server, debug) {
-                Ok(commands) => {
-                    if let Some(commands) = commands {
-                        let last_euler = euler_receiver.try_recv().ok();
+    if devices
+        .iter()
+        .filter(|device| device.model.is_some())
+        .count()
+        > 1
+    {
+        println!();
+        println!(
+            "several glasses attached, the daemon only starts streaming from the marked ones \
+             once the others are unplugged"
+        );
+    }
 
-                        if debug {
-                            println!("received command: {commands:#?}");
-                        }
+    if devices.iter().any(|device| device.model.is_none()) {
+        println!();
+        println!("unknown models are not supported yet, please report them with the line above");
+    }
 
-                        euler_handler
-                            .lock()
-                            .unwrap()
-                            .apply_commands(commands, last_euler);
-                    }
-                }
-                Err(err) => {
-                    if debug {
-                        println!("tcp error: {err:?}");
-                    }
-                }
+    ExitCode::SUCCESS
+}
+
+fn record_poses(config: &Config, rate: f32, output: Option<&Path>) -> ExitCode {
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => match File::create(path) {
+            Ok(file) => Box::new(file),
+            Err(err) => {
+                eprintln!("failed to create {}: {err}", path.display());
+                return ExitCode::FAILURE;
             }
-        }
+        },
+        None => Box::new(io::stdout()),
+    };
+
+    let result = config.control.connect().and_then(|mut client| {
+        client.subscribe(rate, |pose| {
+            writeln!(out, "{}", to_string(&pose)?)?;
+            out.flush()?;
+
+            Ok(())
+        })
     });
 
-    loop {
-        let mut euler_data = receiver.recv()?;
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("recording failed: {err:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn print_profiles(config: &Config) -> ExitCode {
+    request(config, RequestBody::Profiles, |data| {
+        let Some(data) = data else {
+            bail!("daemon answered without profiles");
+        };
 
-        euler_sender.send(euler_data)?;
-        euler_data = euler_handler.lock().unwrap().apply_config(euler_data);
+        let list: ProfileList = from_value(data).context("unexpected profile answer")?;
 
-        let open_track_data = OpenTrackData::from_viture_sdk(euler_data, framenumber);
+        for profile in list.profiles {
+            let marker = if profile == list.active { '*' } else { ' ' };
+            println!("{marker} {profile}");
+        }
 
-        if debug && verbose {
-            println!(
-                "yaw: {:.3}, pitch: {:.3}, roll: {:.3}",
-                open_track_data.yaw, open_track_data.pitch, open_track_data.roll
-            );
+        Ok(())
+    })
+}
+
+fn send_request(config: &Config, body: RequestBody) -> ExitCode {
+    request(config, body, |data| {
+        match data {
+            None => println!("ok"),
+            Some(Value::String(data)) => println!("{data}"),
+            Some(data) => println!("{data}"),
         }
 
-        let _ = socket.send(&open_track_data.into_raw());
+        Ok(())
+    })
+}
 
-        framenumber += 1;
+/// Sends one request to the daemon and hands the data of a successful answer, if any, to
+/// `handle`. Exits with 1 on errors and 2 if the daemon can't be reached.
+fn request(
+    config: &Config,
+    body: RequestBody,
+    handle: impl FnOnce(Option<Value>) -> Result<()>,
+) -> ExitCode {
+    let response = config
+        .control
+        .connect()
+        .and_then(|mut client| client.request(body));
+
+    let result = match response {
+        Ok(ResponseBody::Ok) => handle(None),
+        Ok(ResponseBody::Data(data)) => handle(Some(data)),
+        Ok(ResponseBody::Error(reason)) => Err(anyhow!("error: {reason}")),
+        Err(err) => {
+            eprintln!("failed to talk to the daemon: {err:#}");
+            return ExitCode::from(2);
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("{err:#}");
+            ExitCode::FAILURE
+        }
     }
 }
 
-fn check_tcp_command(server: &mut TcpListener, debug: bool) -> Result<Option<Vec<Command>>> {
-    let mut commands = Vec::new();
+fn send_to_opentrack(
+    args: &Args,
+    config: &Config,
+    receiver: LatestReceiver<ImuSample>,
+    status: Arc<DaemonStatus>,
+    metrics: Arc<Metrics>,
+    shutdown: &Shutdown,
+    supervisor: &Supervisor,
+) -> Result<()> {
+    debug!("send to opentrack: start");
+
+    let target = OpenTrackTarget::new(config.target.clone(), metrics.clone());
+    let mut scheduler = config
+        .rate
+        .map(|rate| OutputScheduler::new(rate, config.output_mode))
+        .transpose()?;
+    let relay = config
+        .relay
+        .address
+        .map(|address| RelayInput::new(address, config.relay.fields.clone()))
+        .transpose()?;
+
+    let profiles = config.profiles()?;
+    let euler_handler = EulerHandler::new(EulerState {
+        reference: config.center,
+        settings: profiles.settings(),
+    });
+    let (handler_sender, handler_receiver) = ring_channel(NonZeroUsize::new(1).unwrap());
 
-    let (mut stream, _) = server.accept()?;
+    let (euler_sender, euler_receiver) = ring_channel(NonZeroUsize::new(1).unwrap());
 
-    if debug {
-        println!("incoming stream")
-    }
+    let controller = ControlServer::spawn(
+        Controller::new(
+            euler_handler.clone(),
+            handler_sender,
+            euler_receiver,
+            profiles,
+            ConfigWriter::new(Config::save_path(args.config.as_deref()), config.autosave),
+            status.clone(),
+        ),
+        config.control.socket_path(),
+        config.control.tcp,
+        config.control.token()?,
+        supervisor,
+    )?;
+
+    ProcessWatcher::spawn(config.auto_profile.clone(), controller.clone(), supervisor);
+
+    // the output loop takes over changed configs between two packets
+    let (config_sender, config_receiver) = ring_channel(NonZeroUsize::new(1).unwrap());
+
+    ConfigWatcher::new(Config::paths(args.config.as_deref()))?.spawn(
+        config.clone(),
+        {
+            let args = args.clone();
+            move || args.config()
+        },
+        move |old, new| {
+            if new.control != old.control
+                || new.metrics != old.metrics
+                || new.device != old.device
+                || new.auto_profile != old.auto_profile
+            {
+                warn!(
+                    "changes to `control`, `metrics`, `device` and `auto_profile` apply after a \
+                     restart"
+                );
+            }
 
-    loop {
-        let mut buf = String::new();
-        let len = stream.read_to_string(&mut buf)?;
+            // like the profile, a filter set at runtime stays unless the config changes it
+            if new.log_level != old.log_level {
+                if let Ok(filter) = new.log_filter() {
+                    info!("log filter set to {filter}");
+                    logging::set_filter(filter);
+                }
+            }
 
-        if len == 0 {
-            if debug {
-                println!("received empty message");
+            if let Err(err) = controller.lock().unwrap().reload(old, new) {
+                warn!("failed to apply config: {err:#}");
+                return;
             }
 
-            break;
-        }
+            let _ = config_sender.send((old.clone(), new.clone()));
+        },
+        supervisor,
+    );
 
-        if debug {
-            println!("received message: {buf}");
+    // sockets are bound, systemd may start depending units
+    let mut notifier = Notifier::from_env()?;
+
+    if let Some(notifier) = &mut notifier {
+        notifier.ready(&status)?;
+    }
+
+    let mut output = Output::new(
+        target,
+        relay,
+        euler_handler,
+        handler_receiver,
+        status.clone(),
+        metrics,
+    );
+
+    while !shutdown.requested() {
+        supervisor.check()?;
+
+        if let Some(notifier) = &mut notifier {
+            // a hung or failing usb controller stops the watchdog pings, so systemd restarts
+            if let Err(err) = notifier.tick(&status, status.usb_controller_alive()) {
+                debug!("{err:#}");
+            }
         }
 
-        for split in buf.split(";") {
-            if let Ok(cmd) = from_str(split) {
-                commands.push(cmd);
+        if let Ok((old, new)) = config_receiver.try_recv() {
+            output.reconfigure(&old, &new);
+
+            if new.rate != old.rate || new.output_mode != old.output_mode {
+                match new
+                    .rate
+                    .map(|rate| OutputScheduler::new(rate, new.output_mode))
+                    .transpose()
+                {
+                    Ok(new_scheduler) => {
+                        scheduler = new_scheduler;
+                        status.set_configured_rate(new.rate);
+                    }
+                    Err(err) => warn!("keeping the output rate: {err:#}"),
+                }
             }
         }
-    }
 
-    if debug {
-        println!("received commands: {commands:#?}");
-    }
+        match &mut scheduler {
+            Some(scheduler) => {
+                let timeout = scheduler
+                    .next_tick()
+                    .saturating_duration_since(Instant::now());
 
-    Ok(if commands.is_empty() {
-        None
-    } else {
-        Some(commands)
-    })
-}
+                match receiver.recv_timeout(timeout) {
+                    Ok(sample) => {
+                        euler_sender.send(sample.euler)?;
+                        scheduler.push(sample);
+                    }
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => bail!("imu channel disconnected"),
+                }
 
-fn check_cli_commands(args: &Args) -> Option<Vec<Command>> {
-    let mut commands = Vec::new();
+                let now = Instant::now();
 
-    if args.center {
-        commands.push(Command::Recenter);
-    }
+                if now >= scheduler.next_tick() {
+                    match scheduler.tick(now) {
+                        Some(euler_data) => {
+                            let captured = scheduler.latest_timestamp().unwrap_or(now);
 
-    if let Some(f) = args.scale_pitch {
-        commands.push(Command::ScalePitch(f));
+                            output.send(euler_data, captured, receiver.sent(), receiver.dropped());
+                        }
+                        None => output.idle(receiver.sent(), receiver.dropped()),
+                    }
+                }
+            }
+            // wakes up without glasses too, to keep the watchdog and config changes going
+            None => match receiver.recv_timeout(IDLE_TIMEOUT) {
+                Ok(sample) => {
+                    euler_sender.send(sample.euler)?;
+                    output.send(
+                        sample.euler,
+                        sample.timestamp,
+                        receiver.sent(),
+                        receiver.dropped(),
+                    );
+                }
+                Err(RecvTimeoutError::Timeout) => output.idle(receiver.sent(), receiver.dropped()),
+                Err(RecvTimeoutError::Disconnected) => bail!("imu channel disconnected"),
+            },
+        }
     }
 
-    if let Some(f) = args.scale_roll {
-        commands.push(Command::ScaleRoll(f));
+    if let Some(notifier) = &notifier {
+        let _ = notifier.stopping();
     }
 
-    if let Some(f) = args.scale_yaw {
-        commands.push(Command::ScaleYaw(f));
-    }
+    // the udp sockets close with the output, the listeners with the process. Only the socket
+    // file would be left behind.
+    let _ = fs::remove_file(config.control.socket_path());
 
-    if let Some(i) = args.invert_pitch {
-        commands.push(Command::InvertPitch(i));
-    }
+    Ok(())
+}
 
-    if let Some(i) = args.invert_roll {
-        commands.push(Command::InvertRoll(i));
-    }
+#[cfg(test)]
+mod test {
+    use clap::{CommandFactory, Parser};
 
-    if let Some(i) = args.invert_yaw {
-        commands.push(Command::InvertYaw(i));
-    }
+    use super::{Args, CliCommand, CtlCommand};
 
-    if args.debug {
-        println!("{commands:#?}");
-    }
+    #[test]
+    fn parse_subcommands() {
+        Args::command().debug_assert();
+
+        let args = Args::try_parse_from(["xr", "ctl", "invert-yaw", "true"]).unwrap();
+        assert!(matches!(
+            args.command,
+            CliCommand::Ctl {
+                command: CtlCommand::InvertYaw { invert: true }
+            }
+        ));
+
+        let args = Args::try_parse_from(["xr", "ctl", "scale-pitch", "-1.5"]).unwrap();
+        assert!(matches!(
+            args.command,
+            CliCommand::Ctl {
+                command: CtlCommand::ScalePitch { scale: -1.5 }
+            }
+        ));
+
+        // global options also go after the subcommand
+        let args = Args::try_parse_from(["xr", "run", "--rate", "60", "-d"]).unwrap();
+        assert!(args.debug);
+        assert!(matches!(args.command, CliCommand::Run(run) if run.rate == Some(60.0)));
 
-    if commands.is_empty() {
-        None
-    } else {
-        Some(commands)
+        // starting the daemon is never implied
+        assert!(Args::try_parse_from(["xr"]).is_err());
+        assert!(Args::try_parse_from(["xr", "--rate", "60"]).is_err());
     }
 }
@@ -0,0 +1,311 @@
+use std::{
+    fmt::Write as _,
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU64, Ordering::Relaxed},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use log::{debug, info};
+
+use crate::supervisor::Supervisor;
+
+const PREFIX: &str = "xr_to_opentrack";
+
+/// Counters and histograms of the daemon, exported in the Prometheus text format
+pub struct Metrics {
+    pub imu_samples: Counter,
+    pub queue_drops: Counter,
+
+    pub packets_sent: Counter,
+    pub send_errors: Counter,
+    pub target_reconnects: Counter,
+    pub device_connects: Counter,
+
+    /// Centering, scaling, relay merging and sending of one packet
+    pub processing: Histogram<7>,
+
+    /// From the IMU callback until the packet is sent
+    pub latency: Histogram<8>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            imu_samples: Counter::default(),
+            queue_drops: Counter::default(),
+
+            packets_sent: Counter::default(),
+            send_errors: Counter::default(),
+            target_reconnects: Counter::default(),
+            device_connects: Counter::default(),
+
+            processing: Histogram::new([0.00001, 0.00005, 0.0001, 0.0005, 0.001, 0.005, 0.01]),
+            latency: Histogram::new([0.0005, 0.001, 0.002, 0.005, 0.01, 0.02, 0.05, 0.1]),
+        }
+    }
+}
+
+impl Metrics {
+    /// Longest a scrape may block on a silent or slow client, which holds up all others
+    const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Serves `GET /metrics` on `address` until the process exits. Returns the bound address.
+    pub fn serve(
+        self: &Arc<Self>,
+        address: SocketAddr,
+        supervisor: &Supervisor,
+    ) -> Result<SocketAddr> {
+        let listener = TcpListener::bind(address)
+            .with_context(|| format!("failed to bind metrics endpoint to {address}"))?;
+        let address = listener.local_addr()?;
+
+        info!("serving metrics on http://{address}/metrics");
+
+        let metrics = self.clone();
+
+        supervisor.spawn("metrics endpoint", move || {
+            // scrapes are rare and tiny, one at a time is plenty
+            for stream in listener.incoming() {
+                // only failed accepts end the loop, failed requests are the client's problem
+                if let Err(err) = metrics.answer(stream?) {
+                    debug!("metrics request failed: {err:#}");
+                }
+            }
+
+            Ok(())
+        });
+
+        Ok(address)
+    }
+
+    fn answer(&self, mut stream: TcpStream) -> Result<()> {
+        stream.set_read_timeout(Some(Self::CLIENT_TIMEOUT))?;
+        stream.set_write_timeout(Some(Self::CLIENT_TIMEOUT))?;
+
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+
+        // the headers are of no interest, but have to be read before answering
+        loop {
+            let mut header = String::new();
+
+            if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+                break;
+            }
+        }
+
+        let mut parts = request_line.split_whitespace();
+
+        let (status, body) = match (parts.next(), parts.next()) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", self.render()),
+            (Some("GET"), _) => ("404 Not Found", "not found, try /metrics\n".to_string()),
+            _ => (
+                "405 Method Not Allowed",
+                "only GET is supported\n".to_string(),
+            ),
+        };
+
+        write!(
+            stream,
+            "HTTP/1.1 {status}\r\n\
+             Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n{body}",
+            body.len()
+        )?;
+
+        Ok(())
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+
+        for (name, help, counter) in [
+            (
+                "imu_samples_total",
+                "IMU samples received from the glasses",
+                &self.imu_samples,
+            ),
+            (
+                "queue_drops_total",
+                "IMU samples replaced before the output loop picked them up",
+                &self.queue_drops,
+            ),
+            (
+                "packets_sent_total",
+                "Packets sent to OpenTrack",
+                &self.packets_sent,
+            ),
+            (
+                "send_errors_total",
+                "Packets that could not be sent to OpenTrack",
+                &self.send_errors,
+            ),
+            (
+                "target_reconnects_total",
+                "New sockets to OpenTrack after sending failed",
+                &self.target_reconnects,
+            ),
+            (
+                "device_connects_total",
+                "Times glasses were connected",
+                &self.device_connects,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {PREFIX}_{name} {help}");
+            let _ = writeln!(out, "# TYPE {PREFIX}_{name} counter");
+            let _ = writeln!(out, "{PREFIX}_{name} {}", counter.get());
+        }
+
+        self.processing.render(
+            &mut out,
+            "processing_seconds",
+            "Time to process and send one packet",
+        );
+        self.latency.render(
+            &mut out,
+            "latency_seconds",
+            "Time from the IMU callback until the packet is sent",
+        );
+
+        out
+    }
+}
+
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Relaxed);
+    }
+
+    /// For counts kept elsewhere, like the ones of the IMU channel
+    pub fn set(&self, value: u64) {
+        self.0.store(value, Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Relaxed)
+    }
+}
+
+/// Histogram of durations with fixed bucket bounds in seconds
+pub struct Histogram<const N: usize> {
+    bounds: [f64; N],
+    buckets: [AtomicU64; N],
+
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl<const N: usize> Histogram<N> {
+    pub fn new(bounds: [f64; N]) -> Self {
+        Self {
+            bounds,
+            buckets: [const { AtomicU64::new(0) }; N],
+
+            count: AtomicU64::new(0),
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+
+        // buckets are stored non-cumulative, summed up when rendering
+        if let Some(index) = self.bounds.iter().position(|bound| seconds <= *bound) {
+            self.buckets[index].fetch_add(1, Relaxed);
+        }
+
+        self.count.fetch_add(1, Relaxed);
+        self.sum_nanos
+            .fetch_add(duration.as_nanos() as u64, Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        let _ = writeln!(out, "# HELP {PREFIX}_{name} {help}");
+        let _ = writeln!(out, "# TYPE {PREFIX}_{name} histogram");
+
+        let mut cumulative = 0;
+
+        for (bound, bucket) in self.bounds.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Relaxed);
+            let _ = writeln!(out, "{PREFIX}_{name}_bucket{{le=\"{bound}\"}} {cumulative}");
+        }
+
+        let count = self.count.load(Relaxed);
+        let sum = self.sum_nanos.load(Relaxed) as f64 / 1e9;
+
+        let _ = writeln!(out, "{PREFIX}_{name}_bucket{{le=\"+Inf\"}} {count}");
+        let _ = writeln!(out, "{PREFIX}_{name}_sum {sum}");
+        let _ = writeln!(out, "{PREFIX}_{name}_count {count}");
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+        sync::Arc,
+        time::Duration,
+    };
+
+    use crate::supervisor::Supervisor;
+
+    use super::Metrics;
+
+    #[test]
+    fn scrape_metrics() {
+        let metrics = Arc::new(Metrics::default());
+
+        metrics.packets_sent.inc();
+        metrics.packets_sent.inc();
+        metrics.imu_samples.set(10);
+
+        metrics.latency.observe(Duration::from_micros(1500));
+        metrics.latency.observe(Duration::from_millis(3));
+        metrics.latency.observe(Duration::from_secs(1));
+
+        let address = metrics
+            .serve("127.0.0.1:0".parse().unwrap(), &Supervisor::new())
+            .unwrap();
+
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect(address).unwrap();
+            write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+
+            response
+        };
+
+        let response = get("/metrics");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        for line in [
+            "xr_to_opentrack_packets_sent_total 2\n",
+            "xr_to_opentrack_imu_samples_total 10\n",
+            "xr_to_opentrack_latency_seconds_bucket{le=\"0.001\"} 0\n",
+            "xr_to_opentrack_latency_seconds_bucket{le=\"0.002\"} 1\n",
+            "xr_to_opentrack_latency_seconds_bucket{le=\"0.005\"} 2\n",
+            "xr_to_opentrack_latency_seconds_bucket{le=\"0.1\"} 2\n",
+            "xr_to_opentrack_latency_seconds_bucket{le=\"+Inf\"} 3\n",
+            "xr_to_opentrack_latency_seconds_sum 1.0045\n",
+            "xr_to_opentrack_latency_seconds_count 3\n",
+            "# TYPE xr_to_opentrack_processing_seconds histogram\n",
+        ] {
+            assert!(response.contains(line), "missing {line:?} in {response}");
+        }
+
+        assert!(get("/").starts_with("HTTP/1.1 404"));
+    }
+}
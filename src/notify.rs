@@ -0,0 +1,171 @@
+use std::{
+    env,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    path::Path,
+    process,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+use crate::status::DaemonStatus;
+
+/// Client of the systemd `sd_notify` protocol, for `Type=notify` services
+pub struct Notifier {
+    socket: UnixDatagram,
+    address: SocketAddr,
+
+    watchdog_interval: Option<Duration>,
+    next_ping: Instant,
+
+    last_status: String,
+}
+
+impl Notifier {
+    /// Returns `None` when not started by systemd (or another service manager speaking the
+    /// protocol)
+    pub fn from_env() -> Result<Option<Self>> {
+        let Some(socket) = env::var_os("NOTIFY_SOCKET") else {
+            return Ok(None);
+        };
+
+        let mut notifier = Self::new(Path::new(&socket))?;
+
+        // the watchdog is meant for the main process only
+        let watchdog_pid = env::var("WATCHDOG_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok());
+
+        if watchdog_pid.is_none_or(|pid| pid == process::id()) {
+            notifier.watchdog_interval = env::var("WATCHDOG_USEC")
+                .ok()
+                .and_then(|usec| usec.parse().ok())
+                .map(Duration::from_micros);
+        }
+
+        Ok(Some(notifier))
+    }
+
+    /// `socket` is a path, or an abstract socket name when starting with `@`
+    pub fn new(socket: &Path) -> Result<Self> {
+        let name = socket.as_os_str().as_encoded_bytes();
+
+        let address = match name.strip_prefix(b"@") {
+            Some(abstract_name) => SocketAddr::from_abstract_name(abstract_name)?,
+            None => SocketAddr::from_pathname(socket)?,
+        };
+
+        Ok(Self {
+            socket: UnixDatagram::unbound().context("failed to create notify socket")?,
+            address,
+
+            watchdog_interval: None,
+            next_ping: Instant::now(),
+
+            last_status: String::new(),
+        })
+    }
+
+    pub fn ready(&mut self, status: &DaemonStatus) -> Result<()> {
+        self.last_status = status.summary();
+
+        self.send(&format!("READY=1\nSTATUS={}", self.last_status))
+    }
+
+    pub fn stopping(&self) -> Result<()> {
+        self.send("STOPPING=1")
+    }
+
+    /// Called from the output loop: pings the watchdog at half its interval while `healthy`, and
+    /// reports status changes. A daemon that stops pinging gets restarted by systemd.
+    pub fn tick(&mut self, status: &DaemonStatus, healthy: bool) -> Result<()> {
+        let summary = status.summary();
+
+        if summary != self.last_status {
+            self.send(&format!("STATUS={summary}"))?;
+            self.last_status = summary;
+        }
+
+        if let Some(interval) = self.watchdog_interval.filter(|_| healthy) {
+            let now = Instant::now();
+
+            if now >= self.next_ping {
+                self.send("WATCHDOG=1")?;
+                self.next_ping = now + interval / 2;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn send(&self, message: &str) -> Result<()> {
+        self.socket
+            .send_to_addr(message.as_bytes(), &self.address)
+            .context("failed to notify the service manager")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, os::unix::net::UnixDatagram, process, time::Duration};
+
+    use crate::status::DaemonStatus;
+
+    use super::Notifier;
+
+    #[test]
+    fn notify_socket() {
+        let path = env::temp_dir().join(format!("xr_to_opentrack_notify_{}", process::id()));
+        let service_manager = UnixDatagram::bind(&path).unwrap();
+        service_manager
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+
+        let receive = || {
+            let mut buffer = [0; 256];
+            let len = service_manager.recv(&mut buffer).unwrap();
+
+            String::from_utf8(buffer[..len].to_vec()).unwrap()
+        };
+
+        let status = DaemonStatus::new("127.0.0.1:4242".parse().unwrap(), None);
+
+        let mut notifier = Notifier::new(&path).unwrap();
+        notifier.watchdog_interval = Some(Duration::from_secs(10));
+
+        notifier.ready(&status).unwrap();
+        assert_eq!(
+            receive(),
+            "READY=1\nSTATUS=waiting for glasses, sending to 127.0.0.1:4242"
+        );
+
+        // no pings while unhealthy, status changes are still reported
+        status.set_target("192.168.0.2:4242".parse().unwrap());
+        notifier.tick(&status, false).unwrap();
+        assert_eq!(
+            receive(),
+            "STATUS=waiting for glasses, sending to 192.168.0.2:4242"
+        );
+
+        // first healthy tick pings, the next ones only after half the interval
+        notifier.tick(&status, true).unwrap();
+        assert_eq!(receive(), "WATCHDOG=1");
+
+        status.set_target("192.168.0.3:4242".parse().unwrap());
+        notifier.tick(&status, true).unwrap();
+        assert_eq!(
+            receive(),
+            "STATUS=waiting for glasses, sending to 192.168.0.3:4242"
+        );
+
+        notifier.stopping().unwrap();
+        assert_eq!(receive(), "STOPPING=1");
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,9 +1,10 @@
-use std::mem::transmute;
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 
 use crate::euler::EulerData;
 
-#[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct OpenTrackData {
     pub x: f64,
     pub y: f64,
@@ -14,11 +15,15 @@ pub struct OpenTrackData {
     pub roll: f64,
 
     pub frame_number: u32,
-
-    _padding: u32,
 }
 
 impl OpenTrackData {
+    /// Six doubles for position and rotation
+    pub const POSE_SIZE: usize = 48;
+
+    /// Pose plus the trailing frame number
+    pub const PACKET_SIZE: usize = Self::POSE_SIZE + 4;
+
     pub fn from_viture_sdk(euler: EulerData, frame_number: u32) -> Self {
         Self {
             x: 0.0,
@@ -30,13 +35,176 @@ impl OpenTrackData {
             roll: euler.roll as f64,
 
             frame_number,
+        }
+    }
+
+    /// Encodes the packet in OpenTrack's UDP layout: x, y, z, yaw, pitch, roll as little-endian
+    /// doubles, followed by the frame number as little-endian u32
+    pub fn encode(&self) -> [u8; Self::PACKET_SIZE] {
+        let mut packet = [0; Self::PACKET_SIZE];
+
+        for (i, value) in [self.x, self.y, self.z, self.yaw, self.pitch, self.roll]
+            .into_iter()
+            .enumerate()
+        {
+            packet[i * 8..(i + 1) * 8].copy_from_slice(&value.to_le_bytes());
+        }
+
+        packet[Self::POSE_SIZE..].copy_from_slice(&self.frame_number.to_le_bytes());
+
+        packet
+    }
+
+    /// Decodes a packet of six doubles, optionally followed by the frame number. Packets from
+    /// plain OpenTrack senders lack the frame number, it is 0 then.
+    pub fn decode(packet: &[u8]) -> Result<Self> {
+        if packet.len() != Self::POSE_SIZE && packet.len() != Self::PACKET_SIZE {
+            bail!(
+                "invalid OpenTrack packet length {} (expected {} or {})",
+                packet.len(),
+                Self::POSE_SIZE,
+                Self::PACKET_SIZE
+            );
+        }
+
+        let double = |i: usize| f64::from_le_bytes(packet[i * 8..(i + 1) * 8].try_into().unwrap());
+
+        Ok(Self {
+            x: double(0),
+            y: double(1),
+            z: double(2),
+
+            yaw: double(3),
+            pitch: double(4),
+            roll: double(5),
+
+            frame_number: packet
+                .get(Self::POSE_SIZE..)
+                .filter(|frame_number| !frame_number.is_empty())
+                .map(|frame_number| u32::from_le_bytes(frame_number.try_into().unwrap()))
+                .unwrap_or_default(),
+        })
+    }
+
+    pub fn get(&self, field: PoseField) -> f64 {
+        match field {
+            PoseField::X => self.x,
+            PoseField::Y => self.y,
+            PoseField::Z => self.z,
+            PoseField::Yaw => self.yaw,
+            PoseField::Pitch => self.pitch,
+            PoseField::Roll => self.roll,
+        }
+    }
+
+    pub fn set(&mut self, field: PoseField, value: f64) {
+        match field {
+            PoseField::X => self.x = value,
+            PoseField::Y => self.y = value,
+            PoseField::Z => self.z = value,
+            PoseField::Yaw => self.yaw = value,
+            PoseField::Pitch => self.pitch = value,
+            PoseField::Roll => self.roll = value,
+        }
+    }
+
+    /// Takes the given fields from `other`, keeping the rest
+    pub fn merge(&mut self, other: &Self, fields: &[PoseField]) {
+        for &field in fields {
+            self.set(field, other.get(field));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PoseField {
+    X,
+    Y,
+    Z,
+    Yaw,
+    Pitch,
+    Roll,
+}
+
+#[cfg(test)]
+mod test {
+    use crate::euler::EulerData;
+
+    use super::{OpenTrackData, PoseField};
+
+    fn golden_data() -> OpenTrackData {
+        OpenTrackData {
+            x: 1.0,
+            y: -2.0,
+            z: 0.5,
 
-            _padding: 0,
+            yaw: 90.0,
+            pitch: -45.0,
+            roll: 180.0,
+
+            frame_number: 0x0102_0304,
         }
     }
 
-    pub fn into_raw(self) -> [u8; 52] {
-        let tmp: [u8; 56] = unsafe { transmute(self) };
-        tmp[..52].try_into().unwrap()
+    const GOLDEN_PACKET: [u8; OpenTrackData::PACKET_SIZE] = [
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf0, 0x3f, // x: 1.0
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc0, // y: -2.0
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xe0, 0x3f, // z: 0.5
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x56, 0x40, // yaw: 90.0
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x46, 0xc0, // pitch: -45.0
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x66, 0x40, // roll: 180.0
+        0x04, 0x03, 0x02, 0x01, // frame number
+    ];
+
+    #[test]
+    fn encode_golden() {
+        assert_eq!(golden_data().encode(), GOLDEN_PACKET);
+    }
+
+    #[test]
+    fn decode_golden() {
+        assert_eq!(
+            OpenTrackData::decode(&GOLDEN_PACKET).unwrap(),
+            golden_data()
+        );
+
+        let pose_only = OpenTrackData::decode(&GOLDEN_PACKET[..OpenTrackData::POSE_SIZE]).unwrap();
+        assert_eq!(
+            pose_only,
+            OpenTrackData {
+                frame_number: 0,
+                ..golden_data()
+            }
+        );
+
+        assert!(OpenTrackData::decode(&GOLDEN_PACKET[..40]).is_err());
+        assert!(OpenTrackData::decode(&[0; 56]).is_err());
+    }
+
+    #[test]
+    fn merge_fields() {
+        let mut glasses = OpenTrackData::from_viture_sdk(
+            EulerData {
+                roll: 1.0,
+                pitch: 2.0,
+                yaw: 3.0,
+            },
+            7,
+        );
+
+        let mut webcam = glasses;
+        webcam.x = 10.0;
+        webcam.y = 20.0;
+        webcam.z = 30.0;
+        webcam.yaw = -3.0;
+
+        glasses.merge(&webcam, &[PoseField::X, PoseField::Y, PoseField::Z]);
+
+        assert_eq!(
+            (glasses.x, glasses.y, glasses.z, glasses.yaw),
+            (10.0, 20.0, 30.0, 3.0)
+        );
+        assert_eq!(glasses.frame_number, 7);
     }
 }
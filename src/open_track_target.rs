@@ -0,0 +1,377 @@
+use std::{
+    fmt,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, info, warn};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::metrics::Metrics;
+
+/// Destination of the OpenTrack UDP stream, given as `host:port`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Destination {
+    pub host: String,
+    pub port: u16,
+}
+
+impl Destination {
+    pub const DEFAULT_PORT: u16 = 4242;
+
+    pub fn resolve(&self) -> Result<Vec<SocketAddr>> {
+        let addrs: Vec<SocketAddr> = (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .with_context(|| format!("failed to resolve {self}"))?
+            .collect();
+
+        if addrs.is_empty() {
+            bail!("{self} did not resolve to any address");
+        }
+
+        Ok(addrs)
+    }
+}
+
+impl FromStr for Destination {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+
+        let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+            // bracketed IPv6 literal, e.g. `[::1]:4242`
+            let (host, rest) = rest
+                .split_once(']')
+                .ok_or_else(|| anyhow!("missing closing bracket in {s:?}"))?;
+
+            let port = match rest.strip_prefix(':') {
+                Some(port) => Some(port),
+                None if rest.is_empty() => None,
+                None => bail!("unexpected {rest:?} after address in {s:?}"),
+            };
+
+            (host, port)
+        } else {
+            match s.matches(':').count() {
+                0 => (s, None),
+                1 => {
+                    let (host, port) = s.split_once(':').unwrap();
+                    (host, Some(port))
+                }
+                // bare IPv6 literal without port
+                _ => (s, None),
+            }
+        };
+
+        if host.is_empty() {
+            bail!("missing host in {s:?}");
+        }
+
+        let port = match port {
+            Some(port) => port
+                .parse()
+                .with_context(|| format!("invalid port {port:?} in {s:?}"))?,
+            None => Self::DEFAULT_PORT,
+        };
+
+        Ok(Self {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+impl Serialize for Destination {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Destination {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(|err: anyhow::Error| de::Error::custom(format!("{err:#}")))
+    }
+}
+
+/// UDP connection to OpenTrack, which re-resolves the destination when sending fails and
+/// every now and then, so a host that moved to another address is followed
+pub struct OpenTrackTarget {
+    destination: Destination,
+    socket: Option<UdpSocket>,
+
+    metrics: Arc<Metrics>,
+
+    last_attempt: Option<Instant>,
+
+    /// When to check if the destination still resolves to the connected address
+    next_resolve: Instant,
+}
+
+impl OpenTrackTarget {
+    const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// UDP sends to a stale address mostly succeed, only resolving again notices the move
+    const RESOLVE_INTERVAL: Duration = Duration::from_secs(30);
+
+    /// A destination that doesn't resolve yet (e.g. before the network is up) is retried by
+    /// [`Self::send`]
+    pub fn new(destination: Destination, metrics: Arc<Metrics>) -> Self {
+        let socket = match Self::connect(&destination) {
+            Ok(socket) => Some(socket),
+            Err(err) => {
+                warn!("can't reach {destination} yet, retrying: {err:#}");
+                None
+            }
+        };
+
+        Self {
+            destination,
+            socket,
+
+            metrics,
+
+            last_attempt: Some(Instant::now()),
+
+            next_resolve: Instant::now() + Self::RESOLVE_INTERVAL,
+        }
+    }
+
+    pub fn send(&mut self, data: &[u8]) {
+        if self.socket.is_some() && Instant::now() >= self.next_resolve {
+            self.check_resolution();
+        }
+
+        if self.socket.is_none() {
+            if self
+                .last_attempt
+                .is_some_and(|last| last.elapsed() < Self::RETRY_INTERVAL)
+            {
+                self.metrics.send_errors.inc();
+                return;
+            }
+
+            self.last_attempt = Some(Instant::now());
+
+            match Self::connect(&self.destination) {
+                Ok(socket) => {
+                    self.socket = Some(socket);
+                    self.metrics.target_reconnects.inc();
+
+                    self.next_resolve = Instant::now() + Self::RESOLVE_INTERVAL;
+                }
+                Err(err) => {
+                    debug!("failed to reconnect to {}: {err:?}", self.destination);
+
+                    self.metrics.send_errors.inc();
+                    return;
+                }
+            }
+        }
+
+        if let Some(socket) = &self.socket {
+            match socket.send(data) {
+                Ok(_) => self.metrics.packets_sent.inc(),
+                Err(err) => {
+                    self.metrics.send_errors.inc();
+
+                    // a refused packet only means OpenTrack is not listening (yet), or not at
+                    // this address anymore
+                    if err.kind() == ErrorKind::ConnectionRefused {
+                        self.next_resolve =
+                            self.next_resolve.min(Instant::now() + Self::RETRY_INTERVAL);
+                    } else {
+                        warn!("udp send to {} failed: {err:?}", self.destination);
+
+                        self.socket = None;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Drops the socket if the destination resolves to other addresses than the connected one,
+    /// [`Self::send`] connects again right away then
+    fn check_resolution(&mut self) {
+        self.next_resolve = Instant::now() + Self::RESOLVE_INTERVAL;
+
+        let Some(peer) = self
+            .socket
+            .as_ref()
+            .and_then(|socket| socket.peer_addr().ok())
+        else {
+            return;
+        };
+
+        match self.destination.resolve() {
+            Ok(addrs) if !addrs.contains(&peer) => {
+                info!(
+                    "{} moved from {peer} to {addrs:?}, reconnecting",
+                    self.destination
+                );
+
+                self.socket = None;
+                self.last_attempt = None;
+            }
+            Ok(_) => (),
+            // keep sending to the last known address, the resolver might be down only
+            Err(err) => debug!("failed to resolve {} again: {err:#}", self.destination),
+        }
+    }
+
+    fn connect(destination: &Destination) -> Result<UdpSocket> {
+        let mut last_error = None;
+
+        for addr in destination.resolve()? {
+            let socket = UdpSocket::bind(Self::bind_address(&addr))
+                .and_then(|socket| socket.connect(addr).map(|_| socket));
+
+            match socket {
+                Ok(socket) => {
+                    debug!(
+                        "Connected udp socket {:?} to {addr} ({destination})",
+                        socket.local_addr()
+                    );
+
+                    return Ok(socket);
+                }
+                Err(err) => last_error = Some(err),
+            }
+        }
+
+        Err(anyhow!(
+            "failed to connect to {destination}: {:?}",
+            last_error.unwrap()
+        ))
+    }
+
+    /// Local address matching the family of the destination. Loopback destinations stay on
+    /// loopback, everything else lets the routing table pick the outgoing interface.
+    fn bind_address(destination: &SocketAddr) -> SocketAddr {
+        let ip = match destination.ip() {
+            IpAddr::V4(ip) if ip.is_loopback() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(ip) if ip.is_loopback() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+
+        SocketAddr::new(ip, 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::UdpSocket,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    use crate::metrics::Metrics;
+
+    use super::{Destination, OpenTrackTarget};
+
+    fn destination(host: &str, port: u16) -> Destination {
+        Destination {
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    #[test]
+    fn parse_destination() {
+        assert_eq!(
+            "127.0.0.1:4242".parse::<Destination>().unwrap(),
+            destination("127.0.0.1", 4242)
+        );
+        assert_eq!(
+            "opentrack.local:5000".parse::<Destination>().unwrap(),
+            destination("opentrack.local", 5000)
+        );
+        assert_eq!(
+            "[::1]:4243".parse::<Destination>().unwrap(),
+            destination("::1", 4243)
+        );
+        assert_eq!(
+            "fe80::1".parse::<Destination>().unwrap(),
+            destination("fe80::1", Destination::DEFAULT_PORT)
+        );
+        assert_eq!(
+            "desktop".parse::<Destination>().unwrap(),
+            destination("desktop", Destination::DEFAULT_PORT)
+        );
+
+        assert!("".parse::<Destination>().is_err());
+        assert!(":4242".parse::<Destination>().is_err());
+        assert!("host:port".parse::<Destination>().is_err());
+        assert!("[::1".parse::<Destination>().is_err());
+    }
+
+    #[test]
+    fn display_destination() {
+        assert_eq!(destination("::1", 4242).to_string(), "[::1]:4242");
+        assert_eq!(destination("desktop", 4242).to_string(), "desktop:4242");
+    }
+
+    #[test]
+    fn bind_follows_destination() {
+        let bind = |addr: &str| OpenTrackTarget::bind_address(&addr.parse().unwrap()).to_string();
+
+        assert_eq!(bind("127.0.0.1:4242"), "127.0.0.1:0");
+        assert_eq!(bind("192.168.1.20:4242"), "0.0.0.0:0");
+        assert_eq!(bind("[::1]:4242"), "[::1]:0");
+        assert_eq!(bind("[2001:db8::1]:4242"), "[::]:0");
+    }
+
+    #[test]
+    fn start_without_destination() {
+        let mut target = OpenTrackTarget::new(
+            destination("xr-to-opentrack.invalid", 4242),
+            Arc::new(Metrics::default()),
+        );
+        assert!(target.socket.is_none());
+
+        // counted as failed, the next attempt to resolve only follows after a while
+        target.send(&[0; 8]);
+        assert!(target.socket.is_none());
+    }
+
+    #[test]
+    fn follow_moved_destination() {
+        let metrics = Arc::new(Metrics::default());
+        let old = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let new = UdpSocket::bind("127.0.0.1:0").unwrap();
+        new.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+
+        let mut target = OpenTrackTarget::new(
+            destination("127.0.0.1", old.local_addr().unwrap().port()),
+            metrics.clone(),
+        );
+
+        // as if the host resolved to another address now
+        target.destination.port = new.local_addr().unwrap().port();
+        target.next_resolve = Instant::now();
+        target.send(&[7; 8]);
+
+        let mut buf = [0; 8];
+        assert_eq!(new.recv(&mut buf).unwrap(), 8);
+        assert_eq!(buf, [7; 8]);
+        assert_eq!(metrics.target_reconnects.get(), 1);
+    }
+}
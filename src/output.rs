@@ -0,0 +1,133 @@
+use std::{sync::Arc, time::Instant};
+
+use log::{info, trace, warn};
+use ring_channel::RingReceiver;
+
+use crate::{
+    config::Config,
+    euler::{EulerData, EulerHandler},
+    metrics::Metrics,
+    open_track_data::OpenTrackData,
+    open_track_target::OpenTrackTarget,
+    relay::RelayInput,
+    status::{DaemonStatus, OutputMeter},
+};
+
+/// Turns poses into OpenTrack packets: centering, scaling, relay merging and sending
+pub struct Output {
+    target: OpenTrackTarget,
+    relay: Option<RelayInput>,
+
+    // the control thread owns the handler and hands out copies, so sending never waits on it
+    euler_handler: EulerHandler,
+    handler_receiver: RingReceiver<EulerHandler>,
+
+    status: Arc<DaemonStatus>,
+    output_meter: OutputMeter,
+    metrics: Arc<Metrics>,
+
+    frame_number: u32,
+}
+
+impl Output {
+    pub fn new(
+        target: OpenTrackTarget,
+        relay: Option<RelayInput>,
+        euler_handler: EulerHandler,
+        handler_receiver: RingReceiver<EulerHandler>,
+        status: Arc<DaemonStatus>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            target,
+            relay,
+
+            euler_handler,
+            handler_receiver,
+
+            output_meter: OutputMeter::new(status.clone()),
+            status,
+            metrics,
+
+            frame_number: 0,
+        }
+    }
+
+    /// `captured` is when the IMU callback delivered the (latest) sample behind `euler_data`
+    pub fn send(
+        &mut self,
+        euler_data: EulerData,
+        captured: Instant,
+        imu_samples: u64,
+        imu_dropped: u64,
+    ) {
+        let start = Instant::now();
+
+        if let Ok(new_handler) = self.handler_receiver.try_recv() {
+            self.euler_handler.update(new_handler);
+        }
+
+        let processed = self.euler_handler.apply_config(euler_data);
+        self.status
+            .publish_pose(self.frame_number, euler_data, processed);
+
+        let mut open_track_data = OpenTrackData::from_viture_sdk(processed, self.frame_number);
+
+        if let Some(relay) = &mut self.relay {
+            relay.merge_into(&mut open_track_data);
+        }
+
+        trace!(
+            frame_number = self.frame_number;
+            "yaw: {:.3}, pitch: {:.3}, roll: {:.3} (imu samples: {imu_samples}, dropped: {imu_dropped})",
+            open_track_data.yaw, open_track_data.pitch, open_track_data.roll,
+        );
+
+        self.target.send(&open_track_data.encode());
+
+        self.metrics.processing.observe(start.elapsed());
+        self.metrics.latency.observe(captured.elapsed());
+        self.metrics.imu_samples.set(imu_samples);
+        self.metrics.queue_drops.set(imu_dropped);
+        self.output_meter
+            .packet_sent(self.frame_number, imu_samples, imu_dropped);
+
+        self.frame_number += 1;
+    }
+
+    /// Keeps the output rate in the status current while no packets are sent
+    pub fn idle(&mut self, imu_samples: u64, imu_dropped: u64) {
+        self.output_meter.update(imu_samples, imu_dropped);
+    }
+
+    /// Applies target and relay changes of a reloaded config. A target that doesn't resolve is
+    /// retried while sending.
+    pub fn reconfigure(&mut self, old: &Config, new: &Config) {
+        if new.target != old.target {
+            info!("sending to {} now", new.target);
+
+            self.target = OpenTrackTarget::new(new.target.clone(), self.metrics.clone());
+            self.status.set_target(new.target.clone());
+        }
+
+        if new.relay.address != old.relay.address {
+            // release the old address first, it may be bound again
+            self.relay = None;
+
+            match new.relay.address {
+                Some(address) => match RelayInput::new(address, new.relay.fields.clone()) {
+                    Ok(relay) => {
+                        info!("relaying from {address} now");
+                        self.relay = Some(relay);
+                    }
+                    Err(err) => warn!("failed to relay from {address}: {err:#}"),
+                },
+                None => info!("relay disabled"),
+            }
+        } else if new.relay.fields != old.relay.fields {
+            if let Some(relay) = &mut self.relay {
+                relay.set_fields(new.relay.fields.clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::euler::EulerSettings;
+
+/// Profile backed by the `[euler]` table of the config, it always exists
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Named sets of [`EulerSettings`], one of them active
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profiles {
+    active: String,
+
+    /// Chosen by the user, the daemon starts with it. Differs from `active` while an automatic
+    /// switch is in effect.
+    selected: String,
+
+    profiles: BTreeMap<String, EulerSettings>,
+}
+
+/// Answer to a profile listing
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProfileList {
+    pub active: String,
+    pub profiles: Vec<String>,
+}
+
+impl Profiles {
+    pub fn new(
+        active: &str,
+        default: EulerSettings,
+        others: &BTreeMap<String, EulerSettings>,
+    ) -> Result<Self> {
+        let mut profiles = others.clone();
+
+        if profiles
+            .insert(DEFAULT_PROFILE.to_string(), default)
+            .is_some()
+        {
+            bail!("profile `{DEFAULT_PROFILE}` is configured by the `euler` table");
+        }
+
+        for name in profiles.keys() {
+            Self::check_name(name)?;
+        }
+
+        if !profiles.contains_key(active) {
+            bail!("profile `{active}` does not exist");
+        }
+
+        Ok(Self {
+            active: active.to_string(),
+            selected: active.to_string(),
+            profiles,
+        })
+    }
+
+    pub fn active(&self) -> &str {
+        &self.active
+    }
+
+    pub fn selected(&self) -> &str {
+        &self.selected
+    }
+
+    pub fn settings(&self) -> EulerSettings {
+        self.profiles[&self.active]
+    }
+
+    pub fn get(&self, name: &str) -> Option<EulerSettings> {
+        self.profiles.get(name).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &EulerSettings)> {
+        self.profiles
+            .iter()
+            .map(|(name, settings)| (name.as_str(), settings))
+    }
+
+    pub fn list(&self) -> ProfileList {
+        ProfileList {
+            active: self.active.clone(),
+            profiles: self.profiles.keys().cloned().collect(),
+        }
+    }
+
+    /// Keeps runtime tuning of the active profile, so it survives switching back and forth
+    pub fn update_active(&mut self, settings: EulerSettings) {
+        self.profiles.insert(self.active.clone(), settings);
+    }
+
+    /// For a reloaded config: profiles whose settings are the same in `old` keep the runtime
+    /// tuning from `runtime`, only edited ones take the new settings
+    pub fn keep_unchanged(&mut self, old: &Profiles, runtime: &Profiles) {
+        for (name, settings) in &mut self.profiles {
+            if old.get(name) != Some(*settings) {
+                continue;
+            }
+
+            if let Some(tuned) = runtime.get(name) {
+                *settings = tuned;
+            }
+        }
+    }
+
+    /// Activates and selects a profile, as the user's choice
+    pub fn activate(&mut self, name: &str) -> Result<EulerSettings> {
+        let settings = self.switch(name)?;
+        self.selected = name.to_string();
+
+        Ok(settings)
+    }
+
+    /// Activates a profile for the time being, the selected one stays
+    pub fn switch(&mut self, name: &str) -> Result<EulerSettings> {
+        let Some(settings) = self.get(name) else {
+            bail!("profile `{name}` does not exist");
+        };
+
+        self.active = name.to_string();
+
+        Ok(settings)
+    }
+
+    /// Creates a profile with default settings
+    pub fn create(&mut self, name: &str) -> Result<()> {
+        self.insert(name, EulerSettings::default())
+    }
+
+    pub fn clone_profile(&mut self, source: &str, name: &str) -> Result<()> {
+        let Some(settings) = self.get(source) else {
+            bail!("profile `{source}` does not exist");
+        };
+
+        self.insert(name, settings)
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<()> {
+        if name == DEFAULT_PROFILE {
+            bail!("profile `{DEFAULT_PROFILE}` can't be deleted");
+        }
+
+        if name == self.active {
+            bail!("profile `{name}` is active, activate another one first");
+        }
+
+        if name == self.selected {
+            bail!("profile `{name}` is selected to start with, activate another one first");
+        }
+
+        if self.profiles.remove(name).is_none() {
+            bail!("profile `{name}` does not exist");
+        }
+
+        Ok(())
+    }
+
+    fn insert(&mut self, name: &str, settings: EulerSettings) -> Result<()> {
+        Self::check_name(name)?;
+
+        if self.profiles.contains_key(name) {
+            bail!("profile `{name}` already exists");
+        }
+
+        self.profiles.insert(name.to_string(), settings);
+
+        Ok(())
+    }
+
+    /// Names end up as keys in the config, keep them simple
+    fn check_name(name: &str) -> Result<()> {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            bail!("invalid profile name `{name}`, only letters, digits, `-` and `_` are allowed");
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use crate::euler::EulerSettings;
+
+    use super::{Profiles, DEFAULT_PROFILE};
+
+    #[test]
+    fn profile_lifecycle() {
+        let racing = EulerSettings {
+            yaw_scale: 2.0,
+            ..Default::default()
+        };
+        let mut profiles = Profiles::new(
+            "racing",
+            EulerSettings::default(),
+            &BTreeMap::from([("racing".to_string(), racing)]),
+        )
+        .unwrap();
+
+        assert_eq!(profiles.settings(), racing);
+
+        profiles.clone_profile("racing", "flight").unwrap();
+        profiles.create("sim").unwrap();
+        assert!(profiles.create("sim").is_err());
+        assert!(profiles.create("has space").is_err());
+
+        assert_eq!(
+            profiles.list().profiles,
+            vec!["default", "flight", "racing", "sim"]
+        );
+
+        // the active profile keeps runtime changes
+        let tuned = EulerSettings {
+            yaw_scale: 3.0,
+            ..Default::default()
+        };
+        profiles.update_active(tuned);
+
+        assert_eq!(profiles.activate("flight").unwrap(), racing);
+        assert_eq!(profiles.get("racing"), Some(tuned));
+
+        assert!(profiles.delete("flight").is_err());
+        assert!(profiles.delete(DEFAULT_PROFILE).is_err());
+        profiles.delete("racing").unwrap();
+        assert!(profiles.activate("racing").is_err());
+
+        // automatic switches leave the selection alone
+        profiles.switch(DEFAULT_PROFILE).unwrap();
+        assert_eq!(profiles.active(), DEFAULT_PROFILE);
+        assert_eq!(profiles.selected(), "flight");
+        assert!(profiles.delete("flight").is_err());
+    }
+
+    #[test]
+    fn reload_keeps_runtime_tuning() {
+        let racing = EulerSettings {
+            yaw_scale: 2.0,
+            ..Default::default()
+        };
+        let tuned = EulerSettings {
+            yaw_scale: 3.0,
+            ..Default::default()
+        };
+        let edited = EulerSettings {
+            yaw_scale: 4.0,
+            ..Default::default()
+        };
+
+        let others = BTreeMap::from([("racing".to_string(), racing)]);
+        let old = Profiles::new(DEFAULT_PROFILE, EulerSettings::default(), &others).unwrap();
+
+        let mut runtime = Profiles::new("racing", EulerSettings::default(), &others).unwrap();
+        runtime.update_active(tuned);
+        runtime.activate(DEFAULT_PROFILE).unwrap();
+        runtime.update_active(tuned);
+
+        // only `default` was edited in the file
+        let mut new = Profiles::new(DEFAULT_PROFILE, edited, &others).unwrap();
+        new.keep_unchanged(&old, &runtime);
+
+        assert_eq!(new.get("racing"), Some(tuned));
+        assert_eq!(new.get(DEFAULT_PROFILE), Some(edited));
+    }
+
+    #[test]
+    fn invalid_profiles() {
+        let default = EulerSettings::default();
+
+        assert!(Profiles::new("missing", default, &BTreeMap::new()).is_err());
+        assert!(Profiles::new(
+            DEFAULT_PROFILE,
+            default,
+            &BTreeMap::from([(DEFAULT_PROFILE.to_string(), default)])
+        )
+        .is_err());
+    }
+}
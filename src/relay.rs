@@ -0,0 +1,184 @@
+use std::{
+    io::ErrorKind,
+    net::{SocketAddr, UdpSocket},
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicBool, Ordering::Relaxed},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use log::{debug, info, warn};
+use ring_channel::{ring_channel, RingReceiver, RingSender};
+
+use crate::open_track_data::{OpenTrackData, PoseField};
+
+/// Receives OpenTrack UDP packets from another tracker and merges selected fields into our output.
+/// Packets are only sent for poses of the glasses, relay input alone doesn't produce any.
+pub struct RelayInput {
+    fields: Vec<PoseField>,
+    receiver: RingReceiver<(OpenTrackData, Instant)>,
+
+    latest: Option<(OpenTrackData, Instant)>,
+
+    /// Cleared on drop, so the listener releases the socket
+    running: Arc<AtomicBool>,
+    listener: Option<JoinHandle<()>>,
+}
+
+impl RelayInput {
+    /// Relay data older than this is considered gone and not merged anymore
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// How often the listener checks for shutdown, which bounds how long dropping blocks
+    const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+    /// Longest wait between receive attempts while the socket keeps failing
+    const MAX_ERROR_BACKOFF: Duration = Duration::from_secs(2);
+
+    pub fn new(address: SocketAddr, fields: Vec<PoseField>) -> Result<Self> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_read_timeout(Some(Self::POLL_INTERVAL))?;
+
+        let (sender, receiver) = ring_channel(NonZeroUsize::new(1).unwrap());
+        let running = Arc::new(AtomicBool::new(true));
+
+        debug!("listening on {:?}, taking {fields:?}", socket.local_addr());
+
+        let listener = thread::spawn({
+            let running = running.clone();
+
+            move || Self::listen(socket, sender, running)
+        });
+
+        Ok(Self {
+            fields,
+            receiver,
+
+            latest: None,
+
+            running,
+            listener: Some(listener),
+        })
+    }
+
+    pub fn set_fields(&mut self, fields: Vec<PoseField>) {
+        debug!("now taking {fields:?}");
+
+        self.fields = fields;
+    }
+
+    fn listen(
+        socket: UdpSocket,
+        sender: RingSender<(OpenTrackData, Instant)>,
+        running: Arc<AtomicBool>,
+    ) {
+        // a little larger than a packet, so oversized datagrams are recognized as such
+        let mut buf = [0; 64];
+        let mut backoff = Duration::ZERO;
+
+        while running.load(Relaxed) {
+            match socket.recv(&mut buf) {
+                Ok(len) => {
+                    backoff = Duration::ZERO;
+
+                    match OpenTrackData::decode(&buf[..len]) {
+                        Ok(data) => {
+                            if sender.send((data, Instant::now())).is_err() {
+                                return;
+                            }
+                        }
+                        Err(err) => {
+                            debug!("dropped packet: {err}");
+                        }
+                    }
+                }
+                // read timeout, only there to notice the shutdown
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => (),
+                Err(err) => {
+                    // errors that don't go away would otherwise spin the thread
+                    if backoff.is_zero() {
+                        warn!("receive error: {err}, retrying with backoff");
+                        backoff = Self::POLL_INTERVAL;
+                    } else {
+                        debug!("receive error: {err:?}");
+                        backoff = (backoff * 2).min(Self::MAX_ERROR_BACKOFF);
+                    }
+
+                    Self::sleep_while_running(backoff, &running);
+                }
+            }
+        }
+    }
+
+    /// Sleeps in poll intervals, so dropping doesn't have to wait out a long backoff
+    fn sleep_while_running(duration: Duration, running: &AtomicBool) {
+        let end = Instant::now() + duration;
+
+        while running.load(Relaxed) {
+            let left = end.saturating_duration_since(Instant::now());
+
+            if left.is_zero() {
+                return;
+            }
+
+            thread::sleep(left.min(Self::POLL_INTERVAL));
+        }
+    }
+
+    /// Overwrites the selected fields of `data` with the most recent relay input
+    pub fn merge_into(&mut self, data: &mut OpenTrackData) {
+        if let Ok(latest) = self.receiver.try_recv() {
+            if self.latest.is_none() {
+                info!("receiving relay input");
+            }
+
+            self.latest = Some(latest);
+        }
+
+        if let Some((relay, received)) = self.latest {
+            if received.elapsed() > Self::TIMEOUT {
+                info!("relay input timed out");
+
+                self.latest = None;
+                return;
+            }
+
+            data.merge(&relay, &self.fields);
+        }
+    }
+}
+
+impl Drop for RelayInput {
+    /// Waits for the listener to close the socket, so the address can be bound again right away
+    fn drop(&mut self) {
+        self.running.store(false, Relaxed);
+
+        if let Some(listener) = self.listener.take() {
+            let _ = listener.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::UdpSocket;
+
+    use super::RelayInput;
+
+    #[test]
+    fn rebind_after_drop() {
+        let address = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let relay = RelayInput::new(address, Vec::new()).unwrap();
+        drop(relay);
+
+        RelayInput::new(address, Vec::new()).unwrap();
+    }
+}
@@ -0,0 +1,296 @@
+use std::{
+    collections::HashMap,
+    ffi::{CString, OsStr},
+    fs, io, mem,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::ffi::OsStrExt,
+    },
+    path::PathBuf,
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+
+use crate::{config::Config, supervisor::Supervisor};
+
+/// Watches the config files with inotify and hands validated changes to `apply`
+pub struct ConfigWatcher {
+    inotify: OwnedFd,
+    files: Vec<PathBuf>,
+
+    /// Content of every file as of the last accepted config
+    accepted: HashMap<PathBuf, String>,
+}
+
+impl ConfigWatcher {
+    /// Editors save by writing a new file and renaming it over the old one, so the directories
+    /// get watched instead of the files
+    const EVENTS: u32 =
+        libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_CREATE | libc::IN_DELETE;
+
+    /// Time for an editor to finish saving, before the files get read
+    const SETTLE: Duration = Duration::from_millis(100);
+
+    pub fn new(files: Vec<PathBuf>) -> Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+
+        if fd == -1 {
+            return Err(io::Error::last_os_error()).context("failed to initialize inotify");
+        }
+
+        let inotify = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        for dir in files.iter().filter_map(|file| file.parent()) {
+            // a config directory created later isn't picked up, that needs a restart
+            if !dir.is_dir() {
+                continue;
+            }
+
+            let dir_name = CString::new(dir.as_os_str().as_bytes())?;
+            let wd = unsafe {
+                libc::inotify_add_watch(inotify.as_raw_fd(), dir_name.as_ptr(), Self::EVENTS)
+            };
+
+            if wd == -1 {
+                return Err(io::Error::last_os_error())
+                    .with_context(|| format!("failed to watch {}", dir.display()));
+            }
+
+            debug!("watching {} for config changes", dir.display());
+        }
+
+        let accepted = read_files(&files);
+
+        Ok(Self {
+            inotify,
+            files,
+
+            accepted,
+        })
+    }
+
+    /// `load` reads the full config (including command line overrides), `apply` gets the
+    /// previously accepted and the new config. Invalid edits are refused and logged with a diff.
+    pub fn spawn(
+        mut self,
+        mut config: Config,
+        load: impl Fn() -> Result<Config> + Send + 'static,
+        mut apply: impl FnMut(&Config, &Config) + Send + 'static,
+        supervisor: &Supervisor,
+    ) {
+        supervisor.spawn("config watcher", move || loop {
+            self.wait().context("failed to watch the config")?;
+
+            thread::sleep(Self::SETTLE);
+
+            let contents = read_files(&self.files);
+
+            if contents == self.accepted {
+                continue;
+            }
+
+            match load() {
+                Ok(new_config) => {
+                    if new_config != config {
+                        info!("config changed, applying");
+
+                        apply(&config, &new_config);
+                        config = new_config;
+                    }
+
+                    self.accepted = contents;
+                }
+                Err(err) => {
+                    warn!("refusing config change: {err:#}");
+
+                    for file in &self.files {
+                        let old = self
+                            .accepted
+                            .get(file)
+                            .map(String::as_str)
+                            .unwrap_or_default();
+                        let new = contents.get(file).map(String::as_str).unwrap_or_default();
+
+                        if old != new {
+                            warn!(
+                                "refused changes to {}:\n{}",
+                                file.display(),
+                                line_diff(old, new).join("\n")
+                            );
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    /// Blocks until one of the config files was touched
+    fn wait(&mut self) -> Result<()> {
+        let mut buffer = [0u8; 4096];
+
+        loop {
+            let len = unsafe {
+                libc::read(
+                    self.inotify.as_raw_fd(),
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                )
+            };
+
+            if len == -1 {
+                let err = io::Error::last_os_error();
+
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+
+                return Err(err.into());
+            }
+
+            let names = event_names(&buffer[..len as usize]);
+
+            debug!("config directory events: {names:?}");
+
+            if names
+                .iter()
+                .any(|name| self.files.iter().any(|file| file.file_name() == Some(name)))
+            {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn read_files(files: &[PathBuf]) -> HashMap<PathBuf, String> {
+    files
+        .iter()
+        .filter_map(|file| Some((file.clone(), fs::read_to_string(file).ok()?)))
+        .collect()
+}
+
+/// Names of the files inotify reported events for
+fn event_names(mut buffer: &[u8]) -> Vec<&OsStr> {
+    let header = mem::size_of::<libc::inotify_event>();
+    let mut names = Vec::new();
+
+    while buffer.len() >= header {
+        let event: libc::inotify_event =
+            unsafe { std::ptr::read_unaligned(buffer.as_ptr() as *const libc::inotify_event) };
+        let end = (header + event.len as usize).min(buffer.len());
+
+        // the name is padded with NULs
+        let name = &buffer[header..end];
+        let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+
+        if !name.is_empty() {
+            names.push(OsStr::from_bytes(name));
+        }
+
+        buffer = &buffer[end..];
+    }
+
+    names
+}
+
+/// Minimal line diff, `-` for removed and `+` for added lines
+fn line_diff(old: &str, new: &str) -> Vec<String> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // longest common subsequence table, config files are small
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            diff.push(format!("- {}", old[i]));
+            i += 1;
+        } else {
+            diff.push(format!("+ {}", new[j]));
+            j += 1;
+        }
+    }
+
+    diff
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, process, sync::mpsc, time::Duration};
+
+    use crate::{config::Config, supervisor::Supervisor};
+
+    use super::{line_diff, ConfigWatcher};
+
+    #[test]
+    fn reload_on_change() {
+        let dir = env::temp_dir().join(format!("xr_to_opentrack_reload_{}", process::id()));
+        let path = dir.join("config.toml");
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&path, "rate = 60\n").unwrap();
+
+        let config = Config::load(Some(&path)).unwrap();
+        let (sender, receiver) = mpsc::channel();
+
+        ConfigWatcher::new(vec![path.clone()]).unwrap().spawn(
+            config,
+            {
+                let path = path.clone();
+                move || Config::load(Some(&path))
+            },
+            move |old, new| sender.send((old.rate, new.rate)).unwrap(),
+            &Supervisor::new(),
+        );
+
+        // invalid edits are refused, the next valid one compares against the last good config
+        fs::write(&path, "rate = -1\n").unwrap();
+        assert!(receiver.recv_timeout(Duration::from_millis(500)).is_err());
+
+        // editors replace the file instead of writing it in place
+        fs::write(dir.join("config.toml.new"), "rate = 90\n").unwrap();
+        fs::rename(dir.join("config.toml.new"), &path).unwrap();
+
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(5)).unwrap(),
+            (Some(60.0), Some(90.0))
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn diff_lines() {
+        let old = "rate = 60\n[euler]\nyaw_scale = 2.0\nroll_scale = 1.0\n";
+        let new = "rate = 60\n[euler]\nyaw_scale = fast\nroll_scale = 1.0\npitch_scale = 3\n";
+
+        assert_eq!(
+            line_diff(old, new),
+            vec![
+                "- yaw_scale = 2.0",
+                "+ yaw_scale = fast",
+                "+ pitch_scale = 3"
+            ]
+        );
+
+        assert!(line_diff(old, old).is_empty());
+    }
+}
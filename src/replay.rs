@@ -0,0 +1,135 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+    process::ExitCode,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use serde_json::from_str;
+
+use crate::{
+    metrics::Metrics,
+    open_track_data::OpenTrackData,
+    open_track_target::{Destination, OpenTrackTarget},
+    status::PoseUpdate,
+};
+
+/// Sends the processed poses of a recording to `target`, `-` reads the recording from stdin
+pub fn replay(path: &Path, target: Destination, speed: f64) -> ExitCode {
+    let result = if path == Path::new("-") {
+        play(io::stdin().lock(), target, speed)
+    } else {
+        File::open(path)
+            .with_context(|| format!("failed to open {}", path.display()))
+            .and_then(|file| play(BufReader::new(file), target, speed))
+    };
+
+    match result {
+        Ok(count) => {
+            println!("replayed {count} poses");
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("replay failed: {err:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Returns the number of poses sent
+fn play(recording: impl BufRead, target: Destination, speed: f64) -> Result<usize> {
+    if !(speed > 0.0 && speed.is_finite()) {
+        bail!("speed has to be positive, got {speed}");
+    }
+
+    // unlike the daemon, don't wait for the destination to come up
+    target.resolve()?;
+    let mut target = OpenTrackTarget::new(target, Arc::new(Metrics::default()));
+
+    let started = Instant::now();
+    let mut first_time = None;
+    let mut count = 0;
+
+    for (index, line) in recording.lines().enumerate() {
+        let line = line?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let pose: PoseUpdate =
+            from_str(&line).with_context(|| format!("invalid pose in line {}", index + 1))?;
+
+        // recordings start at the daemon's uptime, only the differences count
+        let first_time = *first_time.get_or_insert(pose.time);
+        let due = Duration::try_from_secs_f64((pose.time - first_time) / speed)
+            .with_context(|| format!("invalid time {} in line {}", pose.time, index + 1))?;
+
+        if let Some(wait) = due.checked_sub(started.elapsed()) {
+            thread::sleep(wait);
+        }
+
+        target.send(&OpenTrackData::from_viture_sdk(pose.processed, pose.frame_number).encode());
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::UdpSocket, time::Duration};
+
+    use crate::open_track_data::OpenTrackData;
+
+    use super::play;
+
+    #[test]
+    fn replay_recording() {
+        let opentrack = UdpSocket::bind("127.0.0.1:0").unwrap();
+        opentrack
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+
+        let target = opentrack.local_addr().unwrap().to_string().parse().unwrap();
+
+        let recording = r#"{"time":10.0,"frame_number":1,"raw":{"roll":0.0,"pitch":0.0,"yaw":0.0},"processed":{"roll":1.0,"pitch":2.0,"yaw":3.0}}
+
+{"time":10.02,"frame_number":2,"raw":{"roll":0.0,"pitch":0.0,"yaw":0.0},"processed":{"roll":4.0,"pitch":5.0,"yaw":6.0}}
+"#;
+
+        assert_eq!(play(recording.as_bytes(), target, 10.0).unwrap(), 2);
+
+        let mut packet = [0; OpenTrackData::PACKET_SIZE];
+
+        for (frame_number, yaw) in [(1, 3.0), (2, 6.0)] {
+            opentrack.recv(&mut packet).unwrap();
+
+            let data = OpenTrackData::decode(&packet).unwrap();
+            assert_eq!((data.frame_number, data.yaw), (frame_number, yaw));
+        }
+
+        let target = opentrack.local_addr().unwrap().to_string().parse().unwrap();
+        let err = play("{}\n".as_bytes(), target, 1.0).unwrap_err();
+        assert_eq!(err.to_string(), "invalid pose in line 1");
+
+        // going back in time, or too far ahead to wait for
+        let pose = |time: f64| {
+            format!(
+                r#"{{"time":{time:e},"frame_number":1,"raw":{{"roll":0.0,"pitch":0.0,"yaw":0.0}},"processed":{{"roll":0.0,"pitch":0.0,"yaw":0.0}}}}"#
+            )
+        };
+
+        for time in [5.0, 1e300] {
+            let recording = format!("{}\n{}\n", pose(10.0), pose(time));
+            let target = opentrack.local_addr().unwrap().to_string().parse().unwrap();
+
+            let err = play(recording.as_bytes(), target, 1.0).unwrap_err();
+            assert_eq!(err.to_string(), format!("invalid time {time} in line 2"));
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use std::{
+    ops::RangeInclusive,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::euler::{EulerData, ImuSample};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputMode {
+    /// Repeat the latest pose until a new one arrives
+    Hold,
+
+    /// Blend between the last two poses, trailing the IMU by one sample interval
+    Interpolate,
+}
+
+/// Output clock that emits poses at a fixed rate, independent of the IMU rate
+pub struct OutputScheduler {
+    mode: OutputMode,
+    period: Duration,
+    next_tick: Instant,
+
+    previous: Option<ImuSample>,
+    latest: Option<ImuSample>,
+}
+
+impl OutputScheduler {
+    /// Supported output rates in Hz
+    pub const RATES: RangeInclusive<f32> = 1.0..=1000.0;
+
+    pub fn new(rate: f32, mode: OutputMode) -> Result<Self> {
+        if !Self::RATES.contains(&rate) {
+            bail!(
+                "output rate has to be between {} and {} Hz (got {rate})",
+                Self::RATES.start(),
+                Self::RATES.end()
+            );
+        }
+
+        let period = Duration::try_from_secs_f32(1.0 / rate)?;
+
+        Ok(Self {
+            mode,
+            period,
+            next_tick: Instant::now() + period,
+
+            previous: None,
+            latest: None,
+        })
+    }
+
+    pub fn push(&mut self, sample: ImuSample) {
+        self.previous = self.latest.replace(sample);
+    }
+
+    /// When the newest pushed sample was captured
+    pub fn latest_timestamp(&self) -> Option<Instant> {
+        self.latest.map(|sample| sample.timestamp)
+    }
+
+    pub fn next_tick(&self) -> Instant {
+        self.next_tick
+    }
+
+    /// Advances the clock and returns the pose for the tick at `now`, if any pose is known yet
+    pub fn tick(&mut self, now: Instant) -> Option<EulerData> {
+        self.next_tick += self.period;
+
+        // don't burst to catch up after a stall, just continue from now
+        if self.next_tick <= now {
+            self.next_tick = now + self.period;
+        }
+
+        self.sample_at(now)
+    }
+
+    fn sample_at(&self, now: Instant) -> Option<EulerData> {
+        let latest = self.latest?;
+
+        match (self.mode, self.previous) {
+            (OutputMode::Interpolate, Some(previous)) => {
+                let interval = latest
+                    .timestamp
+                    .saturating_duration_since(previous.timestamp);
+
+                if interval.is_zero() {
+                    return Some(latest.euler);
+                }
+
+                let render_time = now.checked_sub(interval).unwrap_or(now);
+                let t = render_time
+                    .saturating_duration_since(previous.timestamp)
+                    .as_secs_f32()
+                    / interval.as_secs_f32();
+
+                Some(previous.euler.interpolate(latest.euler, t.clamp(0.0, 1.0)))
+            }
+            _ => Some(latest.euler),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::euler::{interpolate_angle, EulerData, ImuSample};
+
+    use super::{OutputMode, OutputScheduler};
+
+    fn sample(angle: f32, timestamp: Instant) -> ImuSample {
+        ImuSample {
+            euler: EulerData {
+                roll: angle,
+                pitch: angle,
+                yaw: angle,
+            },
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn scheduler_hold() {
+        let mut scheduler = OutputScheduler::new(100.0, OutputMode::Hold).unwrap();
+        let start = Instant::now();
+
+        assert_eq!(scheduler.tick(start), None);
+
+        scheduler.push(sample(1.0, start));
+        scheduler.push(sample(2.0, start + Duration::from_millis(10)));
+
+        assert_eq!(
+            scheduler.tick(start + Duration::from_millis(15)),
+            Some(sample(2.0, start).euler)
+        );
+    }
+
+    #[test]
+    fn scheduler_interpolate() {
+        let mut scheduler = OutputScheduler::new(100.0, OutputMode::Interpolate).unwrap();
+        let start = Instant::now();
+
+        scheduler.push(sample(0.0, start));
+        scheduler.push(sample(10.0, start + Duration::from_millis(10)));
+
+        let pose = scheduler.tick(start + Duration::from_millis(15)).unwrap();
+
+        assert!((pose.yaw - 5.0).abs() < 1e-3);
+
+        // never extrapolates past the latest sample
+        let pose = scheduler.tick(start + Duration::from_millis(50)).unwrap();
+
+        assert_eq!(pose, sample(10.0, start).euler);
+    }
+
+    #[test]
+    fn interpolate_wraps_around() {
+        let angle = interpolate_angle(170.0, -170.0, 0.5);
+        assert!((angle.abs() - 180.0).abs() < 1e-3);
+
+        let angle = interpolate_angle(170.0, -170.0, 0.25);
+        assert!((angle - 175.0).abs() < 1e-3);
+
+        let angle = interpolate_angle(-170.0, 170.0, 0.25);
+        assert!((angle + 175.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn tick_skips_after_stall() {
+        let mut scheduler = OutputScheduler::new(100.0, OutputMode::Hold).unwrap();
+        let now = scheduler.next_tick() + Duration::from_secs(1);
+
+        scheduler.tick(now);
+
+        assert_eq!(scheduler.next_tick(), now + scheduler.period);
+    }
+
+    #[test]
+    fn invalid_rate() {
+        assert!(OutputScheduler::new(0.0, OutputMode::Hold).is_err());
+        assert!(OutputScheduler::new(f32::NAN, OutputMode::Hold).is_err());
+        assert!(OutputScheduler::new(f32::INFINITY, OutputMode::Hold).is_err());
+        assert!(OutputScheduler::new(1e-30, OutputMode::Hold).is_err());
+        assert!(OutputScheduler::new(1e30, OutputMode::Hold).is_err());
+
+        assert!(OutputScheduler::new(1.0, OutputMode::Hold).is_ok());
+        assert!(OutputScheduler::new(1000.0, OutputMode::Hold).is_ok());
+    }
+}
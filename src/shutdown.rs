@@ -0,0 +1,94 @@
+use std::{
+    io, mem, process, ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering::SeqCst},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+
+/// Set once SIGINT or SIGTERM arrived, the loops check it and wind down
+#[derive(Clone)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    /// Time the loops get to release the glasses, before the process exits regardless
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Blocks SIGINT and SIGTERM and waits for them on a dedicated thread. Has to be called
+    /// before any other thread is spawned (libusb starts its own), threads inherit the mask.
+    pub fn install() -> Result<Self> {
+        let set = unsafe {
+            let mut set: libc::sigset_t = mem::zeroed();
+
+            libc::sigemptyset(&mut set);
+            libc::sigaddset(&mut set, libc::SIGINT);
+            libc::sigaddset(&mut set, libc::SIGTERM);
+
+            set
+        };
+
+        let res = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut()) };
+
+        if res != 0 {
+            return Err(io::Error::from_raw_os_error(res)).context("failed to block signals");
+        }
+
+        let shutdown = Self {
+            requested: Arc::new(AtomicBool::new(false)),
+        };
+
+        thread::spawn({
+            let shutdown = shutdown.clone();
+
+            move || shutdown.wait_for_signals(set)
+        });
+
+        Ok(shutdown)
+    }
+
+    /// Winds the loops down without a signal, e.g. when the daemon exits on an error
+    pub fn request(&self) {
+        self.requested.store(true, SeqCst);
+    }
+
+    pub fn requested(&self) -> bool {
+        self.requested.load(SeqCst)
+    }
+
+    fn wait_for_signals(&self, set: libc::sigset_t) {
+        loop {
+            let mut signal = 0;
+
+            if unsafe { libc::sigwait(&set, &mut signal) } != 0 {
+                continue;
+            }
+
+            let name = match signal {
+                libc::SIGINT => "SIGINT",
+                libc::SIGTERM => "SIGTERM",
+                _ => "signal",
+            };
+
+            if self.requested.swap(true, SeqCst) {
+                warn!("received {name} again, exiting right away");
+                process::exit(1);
+            }
+
+            info!("received {name}, shutting down");
+
+            thread::spawn(|| {
+                thread::sleep(Self::TIMEOUT);
+
+                error!("shutdown timed out after {:?}", Self::TIMEOUT);
+                process::exit(1);
+            });
+        }
+    }
+}
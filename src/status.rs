@@ -0,0 +1,490 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    euler::{EulerData, EulerState},
+    hotplug::{DeviceInfo, UsbDevice},
+    open_track_target::Destination,
+};
+
+/// Live daemon state that is not owned by the control thread
+pub struct DaemonStatus {
+    started: Instant,
+
+    target: Mutex<Destination>,
+    configured_rate: Mutex<Option<f32>>,
+
+    device: Mutex<Option<DeviceInfo>>,
+    ambiguous: AtomicBool,
+    devices: Mutex<Vec<UsbDevice>>,
+    stream: Mutex<StreamStatus>,
+
+    /// Last loop iteration of the usb controller
+    usb_heartbeat: Mutex<Instant>,
+
+    frame_number: AtomicU32,
+    output_rate: AtomicU32,
+
+    imu_samples: AtomicU64,
+    imu_dropped: AtomicU64,
+
+    subscribers: AtomicUsize,
+    pose: Mutex<Option<PoseUpdate>>,
+}
+
+impl DaemonStatus {
+    const MAX_HEARTBEAT_AGE: Duration = Duration::from_secs(5);
+
+    pub fn new(target: Destination, configured_rate: Option<f32>) -> Self {
+        Self {
+            started: Instant::now(),
+
+            target: Mutex::new(target),
+            configured_rate: Mutex::new(configured_rate),
+
+            device: Mutex::new(None),
+            ambiguous: AtomicBool::new(false),
+            devices: Mutex::new(Vec::new()),
+            stream: Mutex::new(StreamStatus::default()),
+
+            usb_heartbeat: Mutex::new(Instant::now()),
+
+            frame_number: AtomicU32::new(0),
+            output_rate: AtomicU32::new(0.0f32.to_bits()),
+
+            imu_samples: AtomicU64::new(0),
+            imu_dropped: AtomicU64::new(0),
+
+            subscribers: AtomicUsize::new(0),
+            pose: Mutex::new(None),
+        }
+    }
+
+    /// Only stores the pose while someone is subscribed, so the send loop doesn't pay for it
+    pub fn publish_pose(&self, frame_number: u32, raw: EulerData, processed: EulerData) {
+        if self.subscribers.load(Relaxed) == 0 {
+            return;
+        }
+
+        *self.pose.lock().unwrap() = Some(PoseUpdate {
+            time: self.started.elapsed().as_secs_f64(),
+            frame_number,
+            raw,
+            processed,
+        });
+    }
+
+    pub fn subscribe(self: &Arc<Self>) -> PoseSubscription {
+        self.subscribers.fetch_add(1, Relaxed);
+
+        PoseSubscription {
+            status: self.clone(),
+            last_frame: None,
+        }
+    }
+
+    pub fn set_target(&self, target: Destination) {
+        *self.target.lock().unwrap() = target;
+    }
+
+    pub fn set_configured_rate(&self, configured_rate: Option<f32>) {
+        *self.configured_rate.lock().unwrap() = configured_rate;
+    }
+
+    /// Also resets the stream health, it belongs to the previous device
+    pub fn set_device(&self, device: Option<DeviceInfo>) {
+        *self.device.lock().unwrap() = device;
+        self.ambiguous.store(false, Relaxed);
+        *self.stream.lock().unwrap() = StreamStatus::default();
+    }
+
+    /// Not streaming, several glasses are attached and the SDK can't be told which to open
+    pub fn set_ambiguous_device(&self) {
+        *self.device.lock().unwrap() = None;
+        self.ambiguous.store(true, Relaxed);
+        *self.stream.lock().unwrap() = StreamStatus::default();
+    }
+
+    /// Attached Viture devices, whether driving the output or not
+    pub fn set_devices(&self, devices: Vec<UsbDevice>) {
+        *self.devices.lock().unwrap() = devices;
+    }
+
+    pub fn set_stream(&self, stream: StreamStatus) {
+        *self.stream.lock().unwrap() = stream;
+    }
+
+    /// Called by the usb controller on every loop iteration
+    pub fn usb_heartbeat(&self) {
+        *self.usb_heartbeat.lock().unwrap() = Instant::now();
+    }
+
+    /// `false` if the usb controller hung or keeps failing, the SDK (re)initializing blocks it
+    /// for a few seconds at most
+    pub fn usb_controller_alive(&self) -> bool {
+        self.usb_heartbeat.lock().unwrap().elapsed() <= Self::MAX_HEARTBEAT_AGE
+    }
+
+    /// Keeps the attempts of the stall around, until the next one
+    pub fn stream_recovered(&self) {
+        let mut stream = self.stream.lock().unwrap();
+
+        stream.stalled = false;
+        stream.failed = false;
+    }
+
+    /// All recovery attempts are used up, the glasses stay stalled
+    pub fn stream_failed(&self) {
+        self.stream.lock().unwrap().failed = true;
+    }
+
+    /// One line of device and connection state, as reported to systemd
+    pub fn summary(&self) -> String {
+        let target = self.target.lock().unwrap();
+
+        let stream = self.stream.lock().unwrap();
+
+        match *self.device.lock().unwrap() {
+            Some(device) if stream.failed => format!(
+                "{} connected but failed to stream after {} recovery attempts, sending to {target}",
+                device.model, stream.recovery_attempts
+            ),
+            Some(device) if stream.stalled => format!(
+                "{} connected but not streaming (recovery attempt {}), sending to {target}",
+                device.model, stream.recovery_attempts
+            ),
+            Some(device) => format!("{} connected, sending to {target}", device.model),
+            None if self.ambiguous.load(Relaxed) => {
+                format!("several glasses connected, unplug all but one to stream to {target}")
+            }
+            None => format!("waiting for glasses, sending to {target}"),
+        }
+    }
+
+    pub fn snapshot(&self, euler: EulerState, profile: &str) -> Status {
+        Status {
+            uptime: self.started.elapsed().as_secs(),
+            target: self.target.lock().unwrap().to_string(),
+            device: *self.device.lock().unwrap(),
+            ambiguous: self.ambiguous.load(Relaxed),
+            devices: self.devices.lock().unwrap().clone(),
+            stream: self.stream.lock().unwrap().clone(),
+
+            configured_rate: *self.configured_rate.lock().unwrap(),
+            output_rate: f32::from_bits(self.output_rate.load(Relaxed)),
+            frame_number: self.frame_number.load(Relaxed),
+
+            imu_samples: self.imu_samples.load(Relaxed),
+            imu_dropped: self.imu_dropped.load(Relaxed),
+
+            profile: profile.to_string(),
+            euler,
+        }
+    }
+}
+
+/// Measures the output rate of the send loop and publishes it to the [`DaemonStatus`]
+pub struct OutputMeter {
+    status: Arc<DaemonStatus>,
+
+    window_start: Instant,
+    window_packets: u32,
+}
+
+impl OutputMeter {
+    const WINDOW: Duration = Duration::from_secs(1);
+
+    pub fn new(status: Arc<DaemonStatus>) -> Self {
+        Self {
+            status,
+
+            window_start: Instant::now(),
+            window_packets: 0,
+        }
+    }
+
+    pub fn packet_sent(&mut self, frame_number: u32, imu_samples: u64, imu_dropped: u64) {
+        self.status.frame_number.store(frame_number, Relaxed);
+        self.window_packets += 1;
+
+        self.update(imu_samples, imu_dropped);
+    }
+
+    /// Publishes the rate once a window is over. Also called while nothing is sent, so the
+    /// rate drops to 0 instead of showing the last one forever.
+    pub fn update(&mut self, imu_samples: u64, imu_dropped: u64) {
+        let elapsed = self.window_start.elapsed();
+
+        if elapsed >= Self::WINDOW {
+            let rate = self.window_packets as f32 / elapsed.as_secs_f32();
+
+            self.status.output_rate.store(rate.to_bits(), Relaxed);
+            self.status.imu_samples.store(imu_samples, Relaxed);
+            self.status.imu_dropped.store(imu_dropped, Relaxed);
+
+            self.window_start = Instant::now();
+            self.window_packets = 0;
+        }
+    }
+}
+
+/// Keeps poses flowing into the [`DaemonStatus`] for as long as it lives
+pub struct PoseSubscription {
+    status: Arc<DaemonStatus>,
+    last_frame: Option<u32>,
+}
+
+impl PoseSubscription {
+    /// Returns the latest pose, unless it was already returned before
+    pub fn next_pose(&mut self) -> Option<PoseUpdate> {
+        let pose = (*self.status.pose.lock().unwrap())?;
+
+        if self.last_frame == Some(pose.frame_number) {
+            return None;
+        }
+
+        self.last_frame = Some(pose.frame_number);
+
+        Some(pose)
+    }
+}
+
+impl Drop for PoseSubscription {
+    fn drop(&mut self) {
+        self.status.subscribers.fetch_sub(1, Relaxed);
+    }
+}
+
+/// One line of a pose subscription stream
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct PoseUpdate {
+    /// Seconds since the daemon started
+    pub time: f64,
+    pub frame_number: u32,
+
+    /// Pose as received from the glasses (after output scheduling)
+    pub raw: EulerData,
+
+    /// Pose after centering, scaling and inverting
+    pub processed: EulerData,
+}
+
+/// Health of the IMU stream of the connected glasses
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StreamStatus {
+    /// No samples for longer than the stall timeout, recovery is in progress
+    pub stalled: bool,
+
+    /// Recovery attempts of the current or last stall
+    pub recovery_attempts: u32,
+
+    /// Last recovery step and its outcome
+    pub last_recovery: Option<String>,
+
+    /// Stalled and all recovery attempts are used up, until the glasses are plugged in again
+    #[serde(default)]
+    pub failed: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Status {
+    /// Seconds since the daemon started
+    pub uptime: u64,
+    pub target: String,
+    pub device: Option<DeviceInfo>,
+    /// Not streaming: several glasses are attached and the SDK can't be told which to open
+    #[serde(default)]
+    pub ambiguous: bool,
+    #[serde(default)]
+    pub devices: Vec<UsbDevice>,
+    #[serde(default)]
+    pub stream: StreamStatus,
+
+    pub configured_rate: Option<f32>,
+    pub output_rate: f32,
+    pub frame_number: u32,
+
+    pub imu_samples: u64,
+    pub imu_dropped: u64,
+
+    pub profile: String,
+    pub euler: EulerState,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+
+        match &self.device {
+            Some(device) => writeln!(
+                f,
+                "device:      {} ({:04x})",
+                device.model, device.product_id
+            )?,
+            None if self.ambiguous => writeln!(
+                f,
+                "device:      several attached, unplug all but the one to stream from"
+            )?,
+            None => writeln!(f, "device:      not connected")?,
+        }
+
+        if self.device.is_some() {
+            let stream = &self.stream;
+
+            match (&stream.last_recovery, stream.stalled) {
+                (Some(recovery), true) if stream.failed => writeln!(
+                    f,
+                    "stream:      failed after {} attempts ({recovery}), replug the glasses",
+                    stream.recovery_attempts
+                )?,
+                (Some(recovery), true) => writeln!(
+                    f,
+                    "stream:      stalled, attempt {}: {recovery}",
+                    stream.recovery_attempts
+                )?,
+                (Some(recovery), false) => writeln!(
+                    f,
+                    "stream:      ok, recovered after {} attempts ({recovery})",
+                    stream.recovery_attempts
+                )?,
+                (None, _) => writeln!(f, "stream:      ok")?,
+            }
+        }
+
+        for device in &self.devices {
+            let active = self
+                .device
+                .is_some_and(|active| (active.bus, active.address) == (device.bus, device.address));
+
+            writeln!(
+                f,
+                "attached:    {} {device}",
+                if active { '*' } else { ' ' }
+            )?;
+        }
+
+        writeln!(f, "target:      {}", self.target)?;
+        writeln!(
+            f,
+            "uptime:      {}h {:02}m {:02}s",
+            self.uptime / 3600,
+            self.uptime / 60 % 60,
+            self.uptime % 60
+        )?;
+
+        match self.configured_rate {
+            Some(rate) => writeln!(
+                f,
+                "output:      {:.1} Hz (fixed {rate} Hz)",
+                self.output_rate
+            )?,
+            None => writeln!(
+                f,
+                "output:      {:.1} Hz (per imu sample)",
+                self.output_rate
+            )?,
+        }
+
+        writeln!(f, "frame:       {}", self.frame_number)?;
+        writeln!(
+            f,
+            "imu samples: {} ({} dropped)",
+            self.imu_samples, self.imu_dropped
+        )?;
+
+        writeln!(f, "profile:     {}", self.profile)?;
+
+        match self.euler.reference {
+            Some(reference) => writeln!(
+                f,
+                "center:      roll {:.3}, pitch {:.3}, yaw {:.3}",
+                reference.roll, reference.pitch, reference.yaw
+            )?,
+            None => writeln!(f, "center:      none")?,
+        }
+
+        let settings = &self.euler.settings;
+
+        writeln!(
+            f,
+            "scale:       roll {}, pitch {}, yaw {}",
+            settings.roll_scale, settings.pitch_scale, settings.yaw_scale
+        )?;
+        writeln!(
+            f,
+            "invert:      roll {}, pitch {}, yaw {}",
+            yes_no(settings.roll_invert),
+            yes_no(settings.pitch_invert),
+            yes_no(settings.yaw_invert)
+        )?;
+        write!(
+            f,
+            "curve:       {} (smoothing {})",
+            settings.curve, settings.smoothing
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{atomic::Ordering::Relaxed, Arc};
+
+    use crate::euler::EulerData;
+
+    use super::{DaemonStatus, OutputMeter};
+
+    #[test]
+    fn pose_subscription() {
+        let status = Arc::new(DaemonStatus::new("127.0.0.1:4242".parse().unwrap(), None));
+        let euler = EulerData {
+            roll: 1.0,
+            pitch: 2.0,
+            yaw: 3.0,
+        };
+
+        // nobody listens, nothing gets stored
+        status.publish_pose(0, euler, euler);
+
+        let mut subscription = status.subscribe();
+        assert!(subscription.next_pose().is_none());
+
+        status.publish_pose(1, euler, euler);
+        assert_eq!(
+            subscription.next_pose().map(|pose| pose.frame_number),
+            Some(1)
+        );
+
+        // a pose is only handed out once
+        assert!(subscription.next_pose().is_none());
+
+        drop(subscription);
+        status.publish_pose(2, euler, euler);
+        assert_eq!(status.pose.lock().unwrap().unwrap().frame_number, 1);
+    }
+
+    #[test]
+    fn output_rate_decays() {
+        let status = Arc::new(DaemonStatus::new("127.0.0.1:4242".parse().unwrap(), None));
+        let mut meter = OutputMeter::new(status.clone());
+        let output_rate = || f32::from_bits(status.output_rate.load(Relaxed));
+
+        meter.window_start -= OutputMeter::WINDOW;
+        meter.packet_sent(0, 10, 1);
+        assert!(output_rate() > 0.0);
+        assert_eq!(status.imu_samples.load(Relaxed), 10);
+
+        // a window without packets
+        meter.window_start -= OutputMeter::WINDOW;
+        meter.update(12, 1);
+        assert_eq!(output_rate(), 0.0);
+        assert_eq!(status.imu_samples.load(Relaxed), 12);
+    }
+}
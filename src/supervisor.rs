@@ -0,0 +1,153 @@
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc::{channel, Receiver, Sender},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Result};
+use log::{debug, error, warn};
+
+/// Runs the daemon's worker threads and restarts the ones that fail. A worker that keeps
+/// failing is given up on, which takes the whole daemon down.
+pub struct Supervisor {
+    fatal_sender: Sender<String>,
+    fatal_receiver: Receiver<String>,
+
+    first_backoff: Duration,
+}
+
+impl Supervisor {
+    const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+    /// A worker running this long counts as healthy again, its failures are forgotten
+    const STABLE: Duration = Duration::from_secs(60);
+
+    /// Failures in a row, without a stable run in between, before giving up
+    const MAX_FAILURES: u32 = 5;
+
+    pub fn new() -> Self {
+        let (fatal_sender, fatal_receiver) = channel();
+
+        Self {
+            fatal_sender,
+            fatal_receiver,
+
+            first_backoff: Duration::from_secs(1),
+        }
+    }
+
+    /// Runs `work` on its own thread until it returns `Ok`. Errors and panics restart it after
+    /// a growing backoff.
+    pub fn spawn(
+        &self,
+        name: &'static str,
+        mut work: impl FnMut() -> Result<()> + Send + 'static,
+    ) -> JoinHandle<()> {
+        let fatal_sender = self.fatal_sender.clone();
+        let first_backoff = self.first_backoff;
+
+        thread::spawn(move || {
+            let mut failures = 0;
+            let mut backoff = first_backoff;
+
+            loop {
+                let started = Instant::now();
+
+                let err = match panic::catch_unwind(AssertUnwindSafe(&mut work)) {
+                    Ok(Ok(())) => {
+                        debug!("{name} finished");
+                        return;
+                    }
+                    Ok(Err(err)) => err,
+                    Err(panic) => anyhow!("panicked: {}", panic_message(&panic)),
+                };
+
+                if started.elapsed() >= Self::STABLE {
+                    failures = 0;
+                    backoff = first_backoff;
+                }
+
+                failures += 1;
+
+                if failures >= Self::MAX_FAILURES {
+                    error!("{name} failed {failures} times in a row, giving up: {err:#}");
+
+                    let _ = fatal_sender.send(format!("{name} failed: {err:#}"));
+                    return;
+                }
+
+                warn!("{name} failed, restarting in {backoff:?}: {err:#}");
+
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(Self::MAX_BACKOFF);
+            }
+        })
+    }
+
+    /// Fails once a worker was given up on
+    pub fn check(&self) -> Result<()> {
+        match self.fatal_receiver.try_recv() {
+            Ok(reason) => bail!("{reason}"),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+fn panic_message(panic: &Box<dyn Any + Send>) -> &str {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => panic
+            .downcast_ref::<String>()
+            .map_or("unknown panic", String::as_str),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicU32, Ordering::SeqCst},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use anyhow::bail;
+
+    use super::Supervisor;
+
+    #[test]
+    fn restart_failed_worker() {
+        let mut supervisor = Supervisor::new();
+        supervisor.first_backoff = Duration::from_millis(1);
+        let runs = Arc::new(AtomicU32::new(0));
+
+        // fails, panics, then finishes
+        supervisor
+            .spawn("flaky", {
+                let runs = runs.clone();
+
+                move || match runs.fetch_add(1, SeqCst) {
+                    0 => bail!("broken"),
+                    1 => panic!("worse"),
+                    _ => Ok(()),
+                }
+            })
+            .join()
+            .unwrap();
+
+        assert_eq!(runs.load(SeqCst), 3);
+        assert!(supervisor.check().is_ok());
+
+        // a worker that never recovers takes the daemon down
+        supervisor
+            .spawn("broken", || bail!("broken"))
+            .join()
+            .unwrap();
+
+        let err = supervisor.check().unwrap_err();
+        assert_eq!(err.to_string(), "broken failed: broken");
+    }
+}
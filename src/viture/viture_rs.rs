@@ -1,4 +1,5 @@
 use anyhow::{bail, Result};
+use log::warn;
 use std::{slice, sync::Mutex};
 
 use super::viture_sys::*;
@@ -7,8 +8,10 @@ use crate::euler::EulerData;
 static IMU_CALLBACK: Mutex<Option<Box<dyn FnMut(EulerData) -> () + Send + Sync + 'static>>> =
     Mutex::new(None);
 
-#[derive(Clone)]
-pub struct Viture {}
+pub struct Viture {
+    /// 3D mode found at init, restored on drop. `None` if the glasses didn't tell.
+    display_3d: Option<bool>,
+}
 
 impl Viture {
     pub fn new(callback: impl FnMut(EulerData) -> () + Send + Sync + 'static) -> Result<Self> {
@@ -20,15 +23,36 @@ impl Viture {
             bail!("failed to initialize viture sdk");
         }
 
+        let display_3d = Self::display_3d();
+
         let err = unsafe { set_imu(true) };
 
         match err {
             VitureResult::ERR_SUCCESS => (),
 
+            _ => {
+                unsafe { deinit() };
+                bail!("failed to enable imu")
+            }
+        }
+
+        Ok(Self { display_3d })
+    }
+
+    /// Asks the glasses to stream again, without touching the SDK
+    pub fn enable_imu(&self) -> Result<()> {
+        match unsafe { set_imu(true) } {
+            VitureResult::ERR_SUCCESS => Ok(()),
             _ => bail!("failed to enable imu"),
         }
+    }
 
-        Ok(Self {})
+    /// `None` if the state can't be read, e.g. because the glasses are gone already
+    fn display_3d() -> Option<bool> {
+        match unsafe { get_3d_state() } {
+            state if state < 0 => None,
+            state => Some(state == VitureState::On as i32),
+        }
     }
 
     extern "C" fn imu_callback(data: *mut u8, len: u16, _ts: u32) {
@@ -50,6 +74,22 @@ impl Viture {
 
 impl Drop for Viture {
     fn drop(&mut self) {
-        unsafe { deinit() }
+        // leave the glasses as they were found, not streaming
+        unsafe {
+            if !matches!(set_imu(false), VitureResult::ERR_SUCCESS) {
+                warn!("failed to disable imu");
+            }
+
+            // only restored when both states are known, otherwise there's nothing to talk to
+            if let (Some(initial), Some(current)) = (self.display_3d, Self::display_3d()) {
+                if current != initial && !matches!(set_3d(initial), VitureResult::ERR_SUCCESS) {
+                    warn!("failed to restore display mode");
+                }
+            }
+
+            deinit();
+        }
+
+        *IMU_CALLBACK.lock().unwrap() = None;
     }
 }
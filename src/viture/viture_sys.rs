@@ -45,10 +45,12 @@ unsafe extern "C" {
     pub unsafe fn deinit();
 
     pub unsafe fn set_imu(on_off: bool) -> VitureResult;
-    pub unsafe fn get_imu_state() -> VitureState;
+    /// [`VitureState`] as int, below 0 on error
+    pub unsafe fn get_imu_state() -> i32;
 
     pub unsafe fn set_3d(on_off: bool) -> VitureResult;
-    pub unsafe fn get_3d_state() -> VitureState;
+    /// [`VitureState`] as int, below 0 on error
+    pub unsafe fn get_3d_state() -> i32;
 
     pub unsafe fn set_imu_fq(frequency: VitureImuFrequency) -> VitureResult;
     pub unsafe fn get_imu_fq() -> VitureImuFrequency;